@@ -0,0 +1,24 @@
+/* C interface of the danmaku layout engine, built with `--features c-api`. */
+#ifndef DANMAKU_H
+#define DANMAKU_H
+
+#include <stdint.h>
+
+typedef struct danmaku_layout danmaku_layout;
+
+/* width and height are the size in pixels of the video area, font_size <= 0 uses the default. */
+danmaku_layout *danmaku_layout_new(double width, double height, double font_size);
+/* Adds a comment at time seconds, message is UTF-8 and color is 0xRRGGBB. The layout keeps its own
+ * copy of the text until danmaku_layout_free. Returns 0 on success, -1 when layout or message is
+ * NULL or message isn't valid UTF-8. */
+int danmaku_layout_add(danmaku_layout *layout, double time, const char *message, uint32_t color);
+/* Call after seeking to lay out all comments again. Does nothing when layout is NULL. */
+void danmaku_layout_seek(danmaku_layout *layout);
+/* Returns the ASS events at playback position pos, one per line, with coordinates in a
+ * res_x x res_y canvas (either may be NULL). Free the result with danmaku_layout_free_string.
+ * Returns NULL when layout is NULL. */
+char *danmaku_layout_render(danmaku_layout *layout, double pos, double *res_x, double *res_y);
+void danmaku_layout_free_string(char *s);
+void danmaku_layout_free(danmaku_layout *layout);
+
+#endif
@@ -0,0 +1,326 @@
+use crate::{
+    danmaku::{Kind, Source},
+    http::{self, Provider},
+    live::{LiveMessage, Protocol},
+};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+const HEADER_LEN: usize = 16;
+const OP_HEARTBEAT: u32 = 2;
+const OP_MESSAGE: u32 = 5;
+const OP_AUTH: u32 = 7;
+// 认证时请求不压缩的数据包，zlib 与 brotli 压缩的数据包无法解码
+const PROTOVER: u16 = 1;
+
+// 所有接口的响应都包含在 data 中
+#[derive(Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Deserialize)]
+struct RoomInit {
+    room_id: u64,
+}
+
+#[derive(Deserialize)]
+struct DanmuInfo {
+    token: String,
+    host_list: Vec<DanmuHost>,
+}
+
+#[derive(Deserialize)]
+struct DanmuHost {
+    host: String,
+    wss_port: u16,
+}
+
+#[derive(Deserialize)]
+struct History {
+    #[serde(default)]
+    room: Vec<HistoryItem>,
+}
+
+#[derive(Deserialize)]
+struct HistoryItem {
+    text: String,
+}
+
+pub struct Bilibili {
+    room: String,
+    room_id: u64,
+    token: String,
+}
+
+impl Bilibili {
+    // 支持 https://live.bilibili.com/22637261 及短号
+    pub fn detect(url: &str) -> Option<Self> {
+        let (_, rest) = url.split_once("live.bilibili.com/")?;
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        let room = path
+            .split('/')
+            .rfind(|s| !s.is_empty())
+            .filter(|s| s.chars().all(|c| c.is_ascii_digit()))?;
+        Some(Self {
+            room: room.to_string(),
+            room_id: 0,
+            token: String::new(),
+        })
+    }
+}
+
+// 数据包头：总长度、头长度、协议版本、操作码、序号，均为大端序
+fn encode(op: u32, body: &[u8]) -> Message {
+    let mut packet = Vec::with_capacity(HEADER_LEN + body.len());
+    packet.extend(((HEADER_LEN + body.len()) as u32).to_be_bytes());
+    packet.extend((HEADER_LEN as u16).to_be_bytes());
+    packet.extend(PROTOVER.to_be_bytes());
+    packet.extend(op.to_be_bytes());
+    packet.extend(1u32.to_be_bytes());
+    packet.extend(body);
+    Message::binary(packet)
+}
+
+// 普通弹幕的 info 为 [[_, 类型, 字号, 颜色, …], 内容, [用户 ID, 用户名, …], …]
+fn live_message(body: &Value) -> Option<LiveMessage> {
+    let cmd = body["cmd"].as_str()?;
+    // cmd 可能带有 DANMU_MSG:4:0:2:2:2:0 之类的后缀
+    let (message, color, kind) = match cmd.split(':').next()? {
+        // info[0][9] 为 2 时是参与天选时刻抽奖自动发送的弹幕
+        "DANMU_MSG" => {
+            let info = &body["info"];
+            (
+                info[1].as_str()?.to_string(),
+                info[0][3].as_u64().unwrap_or(0xFFFFFF) as u32 & 0xFFFFFF,
+                if info[0][9].as_u64() == Some(2) {
+                    Kind::Lottery
+                } else {
+                    Kind::Chat
+                },
+            )
+        }
+        "SEND_GIFT" => {
+            let data = &body["data"];
+            (
+                format!(
+                    "{} 送出{} ×{}",
+                    data["uname"].as_str().unwrap_or_default(),
+                    data["giftName"].as_str().unwrap_or("礼物"),
+                    data["num"].as_u64().unwrap_or(1)
+                ),
+                0xFFFFFF,
+                Kind::Gift,
+            )
+        }
+        "SUPER_CHAT_MESSAGE" => {
+            let data = &body["data"];
+            (
+                format!(
+                    "{}：{}",
+                    data["user_info"]["uname"].as_str().unwrap_or_default(),
+                    data["message"].as_str()?
+                ),
+                0xFFFFFF,
+                Kind::Paid,
+            )
+        }
+        // 天选时刻与红包抽奖的开始与中奖公告
+        "ANCHOR_LOT_START" => (
+            format!(
+                "天选时刻：{}",
+                body["data"]["award_name"].as_str().unwrap_or_default()
+            ),
+            0xFFFFFF,
+            Kind::Lottery,
+        ),
+        "ANCHOR_LOT_AWARD" => (
+            format!(
+                "天选时刻中奖：{}",
+                body["data"]["award_name"].as_str().unwrap_or_default()
+            ),
+            0xFFFFFF,
+            Kind::Lottery,
+        ),
+        "POPULARITY_RED_POCKET_START" | "POPULARITY_RED_POCKET_NEW" => (
+            format!(
+                "{} 发出了红包",
+                body["data"]["sender_name"].as_str().unwrap_or_default()
+            ),
+            0xFFFFFF,
+            Kind::Lottery,
+        ),
+        "POPULARITY_RED_POCKET_WINNER_LIST" => ("红包中奖名单".into(), 0xFFFFFF, Kind::Lottery),
+        // msg_type 为 1 时是进入直播间，2 为关注
+        "INTERACT_WORD" if body["data"]["msg_type"].as_u64() == Some(1) => (
+            format!(
+                "{} 进入了直播间",
+                body["data"]["uname"].as_str().unwrap_or_default()
+            ),
+            0xFFFFFF,
+            Kind::Enter,
+        ),
+        _ => return None,
+    };
+    Some(LiveMessage {
+        message,
+        color,
+        kind,
+    })
+}
+
+impl Protocol for Bilibili {
+    const SOURCE: Source = Source::Bilibili;
+    const PROVIDER: Provider = Provider::Bilibili;
+    const HEARTBEAT: Duration = Duration::from_secs(30);
+
+    async fn prepare(&mut self) -> Result<String> {
+        if self.room_id == 0 {
+            // 短号需要转换为真实房间号
+            let init: Response<RoomInit> = http::send(
+                Provider::Bilibili,
+                &format!(
+                    "https://api.live.bilibili.com/room/v1/Room/room_init?id={}",
+                    self.room
+                ),
+            )
+            .await?
+            .json()
+            .await?;
+            self.room_id = init.data.room_id;
+        }
+        // 每次连接都重新获取服务器与令牌
+        let info: Response<DanmuInfo> = http::send(
+            Provider::Bilibili,
+            &format!(
+                "https://api.live.bilibili.com/xlive/web-room/v1/index/getDanmuInfo?id={}&type=0",
+                self.room_id
+            ),
+        )
+        .await?
+        .json()
+        .await?;
+        self.token = info.data.token;
+        let host = info
+            .data
+            .host_list
+            .first()
+            .ok_or_else(|| anyhow!("bilibili: no danmaku server"))?;
+        Ok(format!("wss://{}:{}/sub", host.host, host.wss_port))
+    }
+
+    fn handshake(&self) -> Vec<Message> {
+        let auth = json!({
+            "uid": 0,
+            "roomid": self.room_id,
+            "protover": PROTOVER,
+            "platform": "web",
+            "type": 2,
+            "key": self.token,
+        });
+        vec![encode(OP_AUTH, auth.to_string().as_bytes())]
+    }
+
+    fn heartbeat(&self) -> Message {
+        encode(OP_HEARTBEAT, b"[object Object]")
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<LiveMessage>> {
+        let data = message.into_data();
+        let mut messages = Vec::new();
+        let mut rest = &data[..];
+        // 一帧中可能包含多个数据包
+        while rest.len() >= HEADER_LEN {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let header = u16::from_be_bytes(rest[4..6].try_into().unwrap()) as usize;
+            let protover = u16::from_be_bytes(rest[6..8].try_into().unwrap());
+            let op = u32::from_be_bytes(rest[8..12].try_into().unwrap());
+            let packet = rest
+                .get(..len)
+                .filter(|_| len >= HEADER_LEN && header <= len)
+                .ok_or_else(|| anyhow!("bilibili: truncated packet"))?;
+            rest = &rest[len..];
+            if op != OP_MESSAGE {
+                continue;
+            }
+            if protover >= 2 {
+                return Err(anyhow!("bilibili: compressed packet version {}", protover));
+            }
+            let body = serde_json::from_slice::<Value>(&packet[header..])?;
+            messages.extend(live_message(&body).filter(|m| !m.message.is_empty()));
+        }
+        Ok(messages)
+    }
+
+    async fn history(&mut self) -> Result<Vec<LiveMessage>> {
+        let history: Response<History> = http::send(
+            Provider::Bilibili,
+            &format!(
+                "https://api.live.bilibili.com/xlive/web-room/v1/dM/gethistory?roomid={}",
+                self.room_id
+            ),
+        )
+        .await?
+        .json()
+        .await?;
+        Ok(history
+            .data
+            .room
+            .into_iter()
+            .map(|item| LiveMessage {
+                message: item.text,
+                color: 0xFFFFFF,
+                kind: Kind::Chat,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(body: Value) -> Vec<(String, Kind)> {
+        let mut bilibili = Bilibili::detect("https://live.bilibili.com/1").unwrap();
+        bilibili
+            .decode(encode(OP_MESSAGE, body.to_string().as_bytes()))
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.message, m.kind))
+            .collect()
+    }
+
+    #[test]
+    fn lottery() {
+        let info = |kind: u64| {
+            json!([
+                [0, 1, 25, 0xFFFFFF, 0, 0, 0, "", 0, kind],
+                "参与",
+                [1, "观众"]
+            ])
+        };
+        assert_eq!(
+            decode(json!({"cmd": "DANMU_MSG", "info": info(0)})),
+            [("参与".into(), Kind::Chat)]
+        );
+        assert_eq!(
+            decode(json!({"cmd": "DANMU_MSG:4:0:2:2:2:0", "info": info(2)})),
+            [("参与".into(), Kind::Lottery)]
+        );
+        assert_eq!(
+            decode(json!({"cmd": "ANCHOR_LOT_START", "data": {"award_name": "周边"}})),
+            [("天选时刻：周边".into(), Kind::Lottery)]
+        );
+        assert_eq!(
+            decode(json!({"cmd": "ANCHOR_LOT_AWARD", "data": {"award_name": "周边"}})),
+            [("天选时刻中奖：周边".into(), Kind::Lottery)]
+        );
+        assert_eq!(
+            decode(json!({"cmd": "POPULARITY_RED_POCKET_START", "data": {"sender_name": "主播"}})),
+            [("主播 发出了红包".into(), Kind::Lottery)]
+        );
+    }
+}
@@ -0,0 +1,159 @@
+#![allow(clippy::missing_safety_doc)]
+
+// 供其他播放器使用的 C 接口，复用弹幕行分配与 ASS 生成
+// 声明见 include/danmaku.h
+
+use crate::{
+    danmaku::{Danmaku, Kind, Mode, Source, Status, Text},
+    layout::{canvas, events_in, layout_in, reset_status, Params},
+    options::Options,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use std::{
+    ffi::{c_char, c_int, CStr, CString},
+    ptr::null_mut,
+};
+
+pub struct Layout {
+    comments: Vec<Danmaku>,
+    // 弹幕文本，与全局的 TEXT 及其他 Layout 互不影响，随 Layout 一同释放
+    text: String,
+    params: Params,
+    options: Options,
+    rng: StdRng,
+    // 上一次渲染的播放位置，按位置的变化推进弹幕
+    last: Option<f64>,
+}
+
+// width、height 为视频显示区域的像素大小
+#[no_mangle]
+pub extern "C" fn danmaku_layout_new(width: f64, height: f64, font_size: f64) -> *mut Layout {
+    let mut options = Options::default();
+    if font_size > 0. {
+        options.font_size = font_size;
+    }
+    Box::into_raw(Box::new(Layout {
+        comments: Vec::new(),
+        text: String::new(),
+        params: Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: width,
+            osd_height: height,
+            pause: true,
+            ..Default::default()
+        },
+        options,
+        rng: StdRng::from_entropy(),
+        last: None,
+    }))
+}
+
+// ctx 为 danmaku_layout_new 的返回值，message 为 UTF-8 字符串
+// color 为 RRGGBB，成功时返回 0，ctx 或 message 为空时返回 -1
+#[no_mangle]
+pub unsafe extern "C" fn danmaku_layout_add(
+    ctx: *mut Layout,
+    time: f64,
+    message: *const c_char,
+    color: u32,
+) -> c_int {
+    if ctx.is_null() || message.is_null() {
+        return -1;
+    }
+    let this = &mut *ctx;
+    let Ok(message) = CStr::from_ptr(message).to_str() else {
+        return -1;
+    };
+    let index = this.comments.partition_point(|c| c.time <= time);
+    this.comments.insert(
+        index,
+        Danmaku {
+            message: Text::push(&mut this.text, message),
+            sender: Text::push(&mut this.text, ""),
+            count: message.chars().count(),
+            time,
+            r: (color >> 16) as u8,
+            g: (color >> 8) as u8,
+            b: color as u8,
+            source: Source::Unknown,
+            kind: Kind::Chat,
+            mode: Mode::Scroll,
+            blocked: false,
+            status: Status::Uninitialized,
+            new: false,
+        },
+    );
+    0
+}
+
+// ctx 为 danmaku_layout_new 的返回值
+// 跳转后调用，重新分配所有弹幕的位置
+#[no_mangle]
+pub unsafe extern "C" fn danmaku_layout_seek(ctx: *mut Layout) {
+    if ctx.is_null() {
+        return;
+    }
+    let this = &mut *ctx;
+    reset_status(&mut this.comments);
+    this.last = None;
+}
+
+// ctx 为 danmaku_layout_new 的返回值，res_x、res_y 可以为空
+// 返回播放位置 pos 处的 ASS 事件，坐标以 res_x × res_y 为画布，需用 danmaku_layout_free_string 释放
+// ctx 为空时返回空指针
+#[no_mangle]
+pub unsafe extern "C" fn danmaku_layout_render(
+    ctx: *mut Layout,
+    pos: f64,
+    res_x: *mut f64,
+    res_y: *mut f64,
+) -> *mut c_char {
+    if ctx.is_null() {
+        return null_mut();
+    }
+    let this = &mut *ctx;
+    // 不连续的位置视为跳转
+    this.params.step = match this.last.map(|last| pos - last) {
+        Some(step) if (0. ..1.).contains(&step) => step,
+        Some(_) => {
+            reset_status(&mut this.comments);
+            0.
+        }
+        None => 0.,
+    };
+    this.last = Some(pos);
+    let frame = layout_in(
+        &mut this.comments,
+        &this.text,
+        this.params,
+        this.options,
+        pos,
+        &mut this.rng,
+    );
+    let (width, height) = canvas(this.params);
+    if !res_x.is_null() {
+        *res_x = width;
+    }
+    if !res_y.is_null() {
+        *res_y = height;
+    }
+    CString::new(events_in(&this.comments, &this.text, &frame, this.options))
+        .map_or(null_mut(), CString::into_raw)
+}
+
+// s 为 danmaku_layout_render 的返回值
+#[no_mangle]
+pub unsafe extern "C" fn danmaku_layout_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+// ctx 为 danmaku_layout_new 的返回值
+#[no_mangle]
+pub unsafe extern "C" fn danmaku_layout_free(ctx: *mut Layout) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
@@ -1,17 +1,124 @@
 #![allow(unreachable_patterns)]
 #![allow(non_snake_case)]
 #![allow(dead_code)]
-use crate::options::Filter;
+use crate::{
+    http::{self, Provider},
+    log::{log_debug, log_error, log_info},
+    mpv::get_property_f64,
+    options::Filter,
+    series::{load_series, set_style},
+    COMMENTS,
+};
 use anyhow::{anyhow, Result};
-use reqwest::Client;
+use md5::{Digest, Md5};
+use regex::Regex;
+use serde::de::{self, SeqAccess, Visitor};
 use serde::{Deserialize, Deserializer};
-use serde::de::{self, Visitor, SeqAccess};
-use std::{fmt,hint};
-use std::sync::{Arc, LazyLock};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard};
+use std::{error, fmt, hint};
+
+// 相似度与第一个结果相差不超过 CLOSE_CONFIDENCE 的结果最多列出 MAX_CANDIDATES 个
+const MAX_CANDIDATES: usize = 3;
+const CLOSE_CONFIDENCE: f64 = 0.1;
+// 综艺分期列表每页的期数，按日期查找时最多翻的页数
+const ZONGYI_PAGE: usize = 8;
+const ZONGYI_MAX_PAGES: usize = 30;
+// 估算弹幕数量时每条弹幕在响应中的平均字节数
+const AVERAGE_ITEM_SIZE: u64 = 60;
+// 弹弹play 计算文件哈希时读取的长度
+const HASH_SIZE: u64 = 16 << 20;
+const DANDANPLAY_MATCH: &str = "https://api.dandanplay.net/api/v2/match";
+const DANDANPLAY_COMMENT: &str = "https://api.dandanplay.net/api/v2/comment/";
+
+// 所有弹幕文本依次存放在同一个字符串中，避免每条弹幕单独分配内存
+static TEXT: RwLock<String> = RwLock::new(String::new());
+// 选项中按顺序给出的标题改写规则
+static REWRITES: OnceLock<Vec<(Regex, String)>> = OnceLock::new();
+// 当前弹幕的来源平台与匹配到的分集，显示在加载完成的提示中
+static MATCHED: Mutex<Option<Matched>> = Mutex::new(None);
+
+#[derive(Clone)]
+pub struct Matched {
+    pub platform: String,
+    pub episode: Option<String>,
+}
+
+impl Matched {
+    // 以播放地址的域名作为平台名
+    pub fn from_url(url: &str) -> Self {
+        let host = url
+            .split_once("://")
+            .map_or(url, |(_, rest)| rest)
+            .split(['/', '?'])
+            .next()
+            .unwrap_or_default();
+        let platform = match host.rsplit('.').nth(1) {
+            Some("bilibili" | "b23") => "bilibili",
+            Some("iqiyi") => "iqiyi",
+            Some("qq") => "qq",
+            Some("youku") => "youku",
+            Some("mgtv") => "mgtv",
+            Some("dandanplay") => "dandanplay",
+            _ => host,
+        };
+        Self {
+            platform: platform.into(),
+            episode: None,
+        }
+    }
+}
+
+pub fn set_matched(matched: Option<Matched>) {
+    *MATCHED.lock().unwrap() = matched;
+}
+
+pub fn matched() -> Option<Matched> {
+    MATCHED.lock().unwrap().clone()
+}
+
+// 弹幕文本在 TEXT 中的位置
+#[derive(Clone, Copy)]
+pub struct Text {
+    start: u32,
+    len: u32,
+}
+
+impl Text {
+    pub fn new(message: &str) -> Self {
+        Self::push(&mut TEXT.write().unwrap(), message)
+    }
+
+    // 存放在 TEXT 以外的字符串中
+    pub fn push(text: &mut String, message: &str) -> Self {
+        let start = text.len() as u32;
+        text.push_str(message);
+        Self {
+            start,
+            len: message.len() as u32,
+        }
+    }
+
+    pub fn get(self, text: &str) -> &str {
+        let start = self.start as usize;
+        text.get(start..start + self.len as usize)
+            .unwrap_or_default()
+    }
+}
 
+// 渲染期间持有读锁，通过 Text::get 取得弹幕文本
+pub fn text() -> RwLockReadGuard<'static, String> {
+    TEXT.read().unwrap()
+}
 
-// 定义全局的 HTTP 客户端
-static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+// 与弹幕列表一同清空
+pub fn clear_text() {
+    *TEXT.write().unwrap() = String::new();
+}
 
 pub struct StatusInner {
     pub x: f64,
@@ -23,6 +130,8 @@ pub enum Status {
     Status(StatusInner),
     Overlapping,
     Uninitialized,
+    // 没有空闲的行，等待有空闲时从右侧进入
+    Delayed,
 }
 
 impl Status {
@@ -36,19 +145,23 @@ impl Status {
 }
 
 pub struct Danmaku {
-    pub message: String,
+    pub message: Text,
+    // 发送者，未知时为空
+    pub sender: Text,
     pub count: usize,
     pub time: f64,
     pub r: u8,
     pub g: u8,
     pub b: u8,
     pub source: Source,
+    pub kind: Kind,
+    pub mode: Mode,
     pub blocked: bool,
     pub status: Status,
+    // 上次播放该文件后新增的弹幕
+    pub new: bool,
 }
 
-
-
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub enum Source {
     Bilibili,
@@ -58,9 +171,87 @@ pub enum Source {
     IQIYI,
     D,
     Dandan,
+    Douyu,
+    Huya,
     Unknown,
 }
 
+// 直播弹幕的消息类型，由各直播源根据其协议中的消息类型分类
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Kind {
+    Chat,
+    Gift,
+    Enter,
+    Lottery,
+    Paid,
+}
+
+// 滚动，或在顶部、底部居中固定显示
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Mode {
+    Scroll,
+    Top,
+    Bottom,
+}
+
+impl Source {
+    pub const ALL: [Source; 10] = [
+        Source::Bilibili,
+        Source::Gamer,
+        Source::AcFun,
+        Source::QQ,
+        Source::IQIYI,
+        Source::D,
+        Source::Dandan,
+        Source::Douyu,
+        Source::Huya,
+        Source::Unknown,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Source::Bilibili => "bilibili",
+            Source::Gamer => "gamer",
+            Source::AcFun => "acfun",
+            Source::QQ => "qq",
+            Source::IQIYI => "iqiyi",
+            Source::D => "d",
+            Source::Dandan => "dandan",
+            Source::Douyu => "douyu",
+            Source::Huya => "huya",
+            Source::Unknown => "unknown",
+        }
+    }
+
+    // 来源图例中的颜色，顺序为 BBGGRR
+    pub fn color(self) -> &'static str {
+        match self {
+            Source::Bilibili => "D2A100",
+            Source::Gamer => "3C8C00",
+            Source::AcFun => "3C3CFD",
+            Source::QQ => "52C815",
+            Source::IQIYI => "32D200",
+            Source::D => "B06C9B",
+            Source::Dandan => "D8A94A",
+            Source::Douyu => "1B7AFF",
+            Source::Huya => "00B4FF",
+            Source::Unknown => "A0A0A0",
+        }
+    }
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Chat => "chat",
+            Kind::Gift => "gift",
+            Kind::Enter => "enter",
+            Kind::Lottery => "lottery",
+            Kind::Paid => "paid",
+        }
+    }
+}
+
 impl From<&str> for Source {
     fn from(value: &str) -> Self {
         match value.to_ascii_lowercase().as_str() {
@@ -71,13 +262,13 @@ impl From<&str> for Source {
             "iqiyi" => Source::IQIYI,
             "d" => Source::D,
             "dandan" => Source::Dandan,
+            "douyu" => Source::Douyu,
+            "huya" => Source::Huya,
             _ => Source::Unknown,
         }
     }
 }
 
-
-
 // 定义用于解析搜索响应的结构体
 #[derive(Debug, Deserialize)]
 struct SearchResponse {
@@ -105,17 +296,21 @@ enum Row {
 
 #[derive(Debug, Deserialize)]
 struct SeriesRow {
+    titleTxt: Option<String>,
+    seriesSite: Option<String>,
     #[serde(deserialize_with = "deserialize_playlinks")]
     seriesPlaylinks: Vec<Playlink>,
 }
 
 #[derive(Debug, Deserialize)]
 struct MovieRow {
+    titleTxt: Option<String>,
     playlinks: Playlinks,
 }
 
 #[derive(Debug, Deserialize)]
 struct ShowRow {
+    titleTxt: Option<String>,
     id: String,
     year: String,
     vipSite: Vec<String>,
@@ -159,22 +354,144 @@ struct ShowsApiData {
 #[derive(Debug, Deserialize)]
 struct ShowItem {
     url: String,
+    // 播出日期，如 20240511
+    period: Option<String>,
+    // 分期名称，如 第5期上
+    name: Option<String>,
+}
+
+// 综艺同一天播出的上下期与加更
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Upper,
+    Lower,
+    Extra,
+}
+
+impl Part {
+    fn markers(self) -> &'static [&'static str] {
+        match self {
+            Part::Upper => &["期上", "(上)", "（上）", "上篇", "上集"],
+            Part::Lower => &["期下", "(下)", "（下）", "下篇", "下集"],
+            Part::Extra => &["加更"],
+        }
+    }
+
+    fn of(name: &str) -> Option<Self> {
+        [Part::Extra, Part::Upper, Part::Lower]
+            .into_iter()
+            .find(|part| part.markers().iter().any(|m| name.contains(m)))
+    }
+}
+
+// 弹弹play 文件识别的结果，isMatched 为 false 时 matches 为多个候选
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MatchResponse {
+    is_matched: bool,
+    #[serde(default)]
+    matches: Vec<MatchItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MatchItem {
+    episode_id: u64,
+    anime_title: String,
+    episode_title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CommentResponse {
+    comments: Vec<CommentItem>,
 }
 
+// p 为 时间,模式,十进制颜色,用户
 #[derive(Debug, Deserialize)]
-struct DanmakuResponse {
-    danmuku: Vec<DanmakuItem>,
+struct CommentItem {
+    p: String,
+    m: String,
 }
 
 #[derive(Debug, Deserialize)]
 struct DanmakuItem(
     f64,    // time
-    u8,     // type (ignored)
+    u8,     // type，与 DPlayer 相同，0 为滚动，1 为顶部，2 为底部
     String, // color
     String, // message
     String, // user
 );
 
+// 逐块解析弹幕响应中 danmuku 数组的元素，不必等待整个响应下载完成
+#[derive(Default)]
+struct ItemStream {
+    buf: Vec<u8>,
+    pos: usize,
+    start: usize,
+    depth: usize,
+    array: bool,
+    string: bool,
+    escape: bool,
+    finished: bool,
+}
+
+impl ItemStream {
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<DanmakuItem>> {
+        self.buf.extend_from_slice(chunk);
+        let mut items = Vec::new();
+        if !self.array {
+            let key = b"\"danmuku\"";
+            let Some(open) = self
+                .buf
+                .windows(key.len())
+                .position(|w| w == key)
+                .and_then(|i| Some(i + self.buf[i..].iter().position(|&b| b == b'[')?))
+            else {
+                return Ok(items);
+            };
+            self.array = true;
+            self.pos = open + 1;
+        }
+        while self.pos < self.buf.len() && !self.finished {
+            let b = self.buf[self.pos];
+            if self.string {
+                if self.escape {
+                    self.escape = false;
+                } else if b == b'\\' {
+                    self.escape = true;
+                } else if b == b'"' {
+                    self.string = false;
+                }
+            } else {
+                match b {
+                    b'"' => self.string = true,
+                    b'[' => {
+                        if self.depth == 0 {
+                            self.start = self.pos;
+                        }
+                        self.depth += 1;
+                    }
+                    b']' if self.depth == 0 => self.finished = true,
+                    b']' => {
+                        self.depth -= 1;
+                        if self.depth == 0 {
+                            items.push(serde_json::from_slice(&self.buf[self.start..=self.pos])?);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            self.pos += 1;
+        }
+        // 丢弃已解析的部分
+        let consumed = if self.depth > 0 { self.start } else { self.pos };
+        self.buf.drain(..consumed);
+        self.pos -= consumed;
+        self.start -= consumed.min(self.start);
+        Ok(items)
+    }
+}
+
 // 自定义反序列化函数，用于处理可能为字符串或对象的 playlinks
 fn deserialize_playlinks<'de, D>(deserializer: D) -> Result<Vec<Playlink>, D::Error>
 where
@@ -222,12 +539,14 @@ where
     deserializer.deserialize_seq(PlaylinkVisitor)
 }
 
-
 // 辅助结构体
 struct SearchQuery {
     title: String,
     season_number: Option<usize>,
     episode_number: Option<usize>,
+    // 综艺文件名中的播出日期，格式为 YYYYMMDD
+    air_date: Option<String>,
+    part: Option<Part>,
 }
 
 // 解析名称的函数
@@ -256,9 +575,96 @@ fn parse_name(name: &str) -> Result<SearchQuery> {
         title,
         season_number,
         episode_number,
+        air_date: None,
+        part: None,
     })
 }
 
+// 综艺文件名中的播出日期，如 20240511期、2024.05.11
+fn parse_air_date(name: &str) -> Option<String> {
+    let regex =
+        Regex::new(r"(?:^|\D)(20\d{2})[.\-/]?(0[1-9]|1[0-2])[.\-/]?(0[1-9]|[12]\d|3[01])(?:\D|$)")
+            .unwrap();
+    let captures = regex.captures(name)?;
+    Some(format!("{}{}{}", &captures[1], &captures[2], &captures[3]))
+}
+
+// 搜索关键词中保留的标题长度
+const MAX_TITLE_CHARS: usize = 16;
+
+// 去掉文件名中与剧集无关的信息：路径、扩展名、字幕组等括号标签、年份、分辨率与编码
+fn anonymize_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext))
+            if (1..=4).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            stem
+        }
+        _ => name,
+    };
+    let mut stripped = String::new();
+    let mut depth = 0;
+    for c in name.chars() {
+        match c {
+            '[' | '【' | '(' | '（' => depth += 1,
+            ']' | '】' | ')' | '）' => depth = 0.max(depth - 1),
+            _ if depth == 0 => stripped.push(c),
+            _ => (),
+        }
+    }
+    stripped
+        .split([' ', '.', '_'])
+        .filter(|s| !s.is_empty())
+        .enumerate()
+        .filter(|&(i, word)| {
+            let word = word.to_ascii_lowercase();
+            let resolution = word.strip_suffix(['p', 'i']).is_some_and(|n| {
+                (3..=4).contains(&n.len()) && n.chars().all(|c| c.is_ascii_digit())
+            });
+            let year = i > 0
+                && word.len() == 4
+                && (word.starts_with("19") || word.starts_with("20"))
+                && word.chars().all(|c| c.is_ascii_digit());
+            let tag = [
+                "4k", "8k", "x264", "x265", "h264", "h265", "hevc", "avc", "aac", "flac", "hdr",
+                "10bit", "web", "web-dl", "webrip", "bluray", "bdrip", "remux",
+            ]
+            .contains(&word.as_str());
+            !resolution && !year && !tag
+        })
+        .map(|(_, word)| word)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn set_rewrites(rewrites: Vec<(Regex, String)>) {
+    _ = REWRITES.set(rewrites);
+}
+
+// 依次经过改写规则、anonymize_query 与解析，得到搜索使用的标题与集数
+fn parse_query(name: &str, anonymize: bool) -> Result<SearchQuery> {
+    // 非 UTF-8 文件名中无法解码的部分不参与匹配
+    let mut name = name.replace(char::REPLACEMENT_CHARACTER, "");
+    for (regex, replacement) in REWRITES.get().into_iter().flatten() {
+        let rewritten = regex.replace_all(&name, replacement.as_str()).into_owned();
+        if rewritten != name {
+            log_debug(&format!("rewrite {}: {} → {}", regex, name, rewritten));
+            name = rewritten;
+        }
+    }
+    if anonymize {
+        name = anonymize_name(&name);
+    }
+    let mut query = parse_name(&name)?;
+    query.air_date = parse_air_date(&name);
+    query.part = Part::of(&name);
+    if anonymize {
+        query.title = query.title.chars().take(MAX_TITLE_CHARS).collect();
+    }
+    Ok(query)
+}
+
 // 构建搜索 URL 的函数
 fn construct_search_url(query: &SearchQuery) -> String {
     if let Some(season_number) = query.season_number {
@@ -274,74 +680,177 @@ fn construct_search_url(query: &SearchQuery) -> String {
     }
 }
 
-// 提取播放链接的函数
-async fn extract_play_url(
-    search_response: &SearchResponse,
-    episode_number: usize,
-) -> Result<String> {
-    let long_data = search_response
+// 搜索时发送给 360kan 的关键词与使用的集数
+pub fn search_keyword(name: &str, anonymize: bool) -> Result<(String, usize)> {
+    let query = parse_query(name, anonymize)?;
+    let keyword = match query.season_number {
+        Some(season_number) => format!("{}{}", query.title, season_number),
+        None => query.title,
+    };
+    Ok((keyword, query.episode_number.unwrap_or(1)))
+}
+
+// 360kan 的播放平台
+pub const SITES: [&str; 5] = ["bilibili1", "qiyi", "qq", "youku", "imgo"];
+
+// 平台的优先级，platform 排在最前，其次按 platform_order 的顺序，未列出的保持默认顺序
+fn site_rank(site: &str, order: &[String], platform: Option<&str>) -> (bool, usize) {
+    (
+        Some(site) != platform,
+        order.iter().position(|o| o == site).unwrap_or(order.len()),
+    )
+}
+
+// 搜索结果的各行，至少有一行
+fn search_rows(search_response: &SearchResponse) -> Result<&[Row]> {
+    search_response
         .data
         .longData
         .as_ref()
-        .ok_or_else(|| anyhow!("Cannot find the series"))?;
+        .map(|data| &data.rows[..])
+        .filter(|rows| !rows.is_empty())
+        .ok_or_else(|| anyhow!("Cannot find the series"))
+}
 
-    let first_row = long_data
-        .rows
-        .get(0)
-        .ok_or_else(|| anyhow!("Cannot find the series"))?;
+fn row_title(row: &Row) -> Option<String> {
+    match row {
+        Row::Series(row) => row.titleTxt.clone(),
+        Row::Movie(row) => row.titleTxt.clone(),
+        Row::Show(row) => row.titleTxt.clone(),
+        _ => None,
+    }
+}
 
-    match first_row {
+// 提取播放链接的函数，同时返回匹配到的标题
+async fn extract_play_url(
+    row: &Row,
+    query: &SearchQuery,
+    order: &[String],
+    platform: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let title = row_title(row);
+    let episode_number = query.episode_number.unwrap_or(1);
+    let url = match row {
         Row::Series(series_row) => {
+            // 剧集的搜索结果只包含一个平台的分集地址
+            if let Some(platform) = platform.filter(|&p| {
+                series_row
+                    .seriesSite
+                    .as_deref()
+                    .is_some_and(|site| site != p)
+            }) {
+                log_info(&format!(
+                    "platform {} not available for this series, using {}",
+                    platform,
+                    series_row.seriesSite.as_deref().unwrap_or_default()
+                ));
+            }
             if episode_number > series_row.seriesPlaylinks.len() {
                 return Err(anyhow!("Episode number out of range"));
             }
             Ok(series_row.seriesPlaylinks[episode_number - 1].url.clone())
         }
         Row::Movie(movie_row) => {
-            movie_row
-                .playlinks
-                .bilibili1
-                .clone()
-                .or_else(|| movie_row.playlinks.qiyi.clone())
-                .or_else(|| movie_row.playlinks.qq.clone())
-                .or_else(|| movie_row.playlinks.youku.clone())
-                .or_else(|| movie_row.playlinks.imgo.clone())
+            let links = &movie_row.playlinks;
+            let mut sites = SITES;
+            sites.sort_by_key(|site| site_rank(site, order, platform));
+            sites
+                .into_iter()
+                .find_map(|site| match site {
+                    "bilibili1" => links.bilibili1.clone(),
+                    "qiyi" => links.qiyi.clone(),
+                    "qq" => links.qq.clone(),
+                    "youku" => links.youku.clone(),
+                    "imgo" => links.imgo.clone(),
+                    _ => None,
+                })
                 .ok_or_else(|| anyhow!("No links available"))
         }
-        Row::Show(show_row) => {
-            extract_play_url_from_show(show_row, episode_number).await
-        }
-        _ => Err(anyhow!("First row does not contain valid playlinks")),
+        Row::Show(show_row) => extract_play_url_from_show(show_row, query, order, platform).await,
+        _ => Err(anyhow!("Row does not contain valid playlinks")),
+    }?;
+    Ok((url, title))
+}
+
+// 搜索结果的标题与搜索关键词的相似度，0 到 1
+fn confidence(query: &SearchQuery, title: Option<&str>) -> f64 {
+    // 搜索结果没有标题时无法判断
+    let Some(title) = title else {
+        return 0.5;
+    };
+    let normalize = |s: &str| {
+        s.chars()
+            .filter(|c| c.is_alphanumeric())
+            .flat_map(char::to_lowercase)
+            .collect::<Vec<_>>()
+    };
+    let keyword = match query.season_number {
+        Some(season_number) => format!("{}{}", query.title, season_number),
+        None => query.title.clone(),
+    };
+    let (a, b) = (normalize(&keyword), normalize(title));
+    if a.is_empty() || b.is_empty() {
+        return 0.;
+    }
+    if a == b {
+        return 1.;
+    }
+    // 共有字符数的 Dice 系数，一方包含另一方时至少为 0.8
+    let mut rest = b.clone();
+    let common = a
+        .iter()
+        .filter(|c| {
+            rest.iter()
+                .position(|d| d == *c)
+                .map(|i| rest.swap_remove(i))
+                .is_some()
+        })
+        .count();
+    let dice = 2. * common as f64 / (a.len() + b.len()) as f64;
+    let (short, long) = if a.len() < b.len() {
+        (&a, &b)
+    } else {
+        (&b, &a)
+    };
+    if long.windows(short.len()).any(|w| w == short.as_slice()) {
+        dice.max(0.8)
+    } else {
+        dice
     }
 }
 
 // 处理 Row::Show 的辅助函数
 async fn extract_play_url_from_show(
     show_row: &ShowRow,
-    episode_number: usize,
+    query: &SearchQuery,
+    order: &[String],
+    platform: Option<&str>,
 ) -> Result<String> {
-    let fields = vec![
+    let mut fields = vec![
         ("bilibili1", show_row.playlinks_total.bilibili1),
         ("imgo", show_row.playlinks_total.imgo),
         ("qiyi", show_row.playlinks_total.qiyi),
         ("qq", show_row.playlinks_total.qq),
         ("youku", show_row.playlinks_total.youku),
     ];
+    fields.sort_by_key(|&(name, _)| site_rank(name, order, platform));
 
     // 过滤出有值的字段名
     let vipsites: Vec<&str> = fields
         .into_iter()
-        .filter_map(|(name, value)| {
-            if value.is_some() {
-                Some(name)
-            } else {
-                None
-            }
-        })
+        .filter_map(
+            |(name, value)| {
+                if value.is_some() {
+                    Some(name)
+                } else {
+                    None
+                }
+            },
+        )
         .collect();
 
     let vipsite = vipsites
-        .get(0)
+        .first()
         .ok_or_else(|| anyhow!("Cannot find the vipsite"))?;
 
     let year = show_row
@@ -354,123 +863,895 @@ async fn extract_play_url_from_show(
         .parse::<i32>()
         .map_err(|_| anyhow!("Invalid id format"))?;
 
+    // 有播出日期时按日期查找，上下期与特别篇使总期数与期号对不上
+    if let Some(date) = &query.air_date {
+        // 列表按播出日期从新到旧排列，翻页直到越过该日期，同一天的各期可能跨页
+        // 每次同时请求 max_requests 页，找到后丢弃多请求的页
+        let mut found = Vec::new();
+        let mut pages = (0..ZONGYI_MAX_PAGES).peekable();
+        'pages: while pages.peek().is_some() {
+            let batch = pages.by_ref().take(http::max_requests()).map(|page| {
+                zongyi_page(
+                    vipsite.to_string(),
+                    date[..4].to_string(),
+                    entid,
+                    page * ZONGYI_PAGE,
+                )
+            });
+            for items in http::fetch_all(batch).await {
+                let items = items?;
+                let last = items.len() < ZONGYI_PAGE
+                    || items
+                        .last()
+                        .and_then(|item| item.period.as_ref())
+                        .is_some_and(|period| period < date);
+                let after = items
+                    .last()
+                    .is_some_and(|item| item.period.as_ref() != Some(date));
+                found.extend(
+                    items
+                        .into_iter()
+                        .filter(|item| item.period.as_ref() == Some(date)),
+                );
+                if last || !found.is_empty() && after {
+                    break 'pages;
+                }
+            }
+        }
+        if found.is_empty() {
+            return Err(anyhow!("No episode aired on {}", date));
+        }
+        return select_part(found, query.part, date);
+    }
+
+    let episode_number = query.episode_number.unwrap_or(1);
     let total_number = show_row
         .playlinks_total
         .bilibili1
-        .clone()
-        .or_else(|| show_row.playlinks_total.qq.clone())
-        .or_else(|| show_row.playlinks_total.youku.clone())
-        .or_else(|| show_row.playlinks_total.qiyi.clone())
-        .or_else(|| show_row.playlinks_total.imgo.clone())
+        .or(show_row.playlinks_total.qq)
+        .or(show_row.playlinks_total.youku)
+        .or(show_row.playlinks_total.qiyi)
+        .or(show_row.playlinks_total.imgo)
         .unwrap_or(0);
 
-    if episode_number > total_number as usize {
+    if episode_number == 0 || episode_number > total_number as usize {
         return Err(anyhow!("Episode number out of range"));
     }
 
+    // 请求对齐到页的起点，取其中对应位置的一期，而不是直接使用返回的第一项
     let offset = (total_number as usize) - episode_number;
+    let items = zongyi_page(
+        vipsite.to_string(),
+        year.to_string(),
+        entid,
+        offset / ZONGYI_PAGE * ZONGYI_PAGE,
+    )
+    .await?;
+    let item = items
+        .get(offset % ZONGYI_PAGE)
+        .ok_or_else(|| anyhow!("Cannot find episode {} in the listing", episode_number))?;
+    log_debug(&format!(
+        "zongyi episode {}: aired {}",
+        episode_number,
+        item.period.as_deref().unwrap_or("unknown")
+    ));
+    // 指定了上下期时在同一天播出的各期中选择
+    match (query.part, item.period.clone()) {
+        (Some(_), Some(period)) => {
+            let found = items
+                .into_iter()
+                .filter(|item| item.period.as_ref() == Some(&period))
+                .collect();
+            select_part(found, query.part, &period)
+        }
+        _ => Ok(item.url.clone()),
+    }
+}
+
+// 在同一天播出的各期中选择 part 对应的一期，无法确定时返回 Ambiguous
+fn select_part(found: Vec<ShowItem>, part: Option<Part>, date: &str) -> Result<String> {
+    let part_of = |item: &ShowItem| item.name.as_deref().and_then(Part::of);
+    let mut matching = found
+        .iter()
+        .filter(|item| part_of(item) == part)
+        .collect::<Vec<_>>();
+    // 未指定时只有一期，或只有一期不是上下期与加更
+    if matching.is_empty() && part.is_none() && found.len() == 1 {
+        matching.push(&found[0]);
+    }
+    if let [item] = matching[..] {
+        return Ok(item.url.clone());
+    }
+    Err(Ambiguous {
+        date: date.into(),
+        candidates: found
+            .into_iter()
+            .map(|item| (item.name.unwrap_or_default(), item.url))
+            .collect(),
+    }
+    .into())
+}
+
+// 同一天播出了多期且无法从标题判断时返回的错误，需要用户确认
+#[derive(Debug)]
+pub struct Ambiguous {
+    pub date: String,
+    // 各期的名称与地址
+    pub candidates: Vec<(String, String)>,
+}
+
+impl fmt::Display for Ambiguous {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} issues aired on {}: {}",
+            self.candidates.len(),
+            self.date,
+            self.candidates
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    }
+}
+
+impl error::Error for Ambiguous {}
+
+// 综艺某一年的分期列表中从 offset 开始的一页
+async fn zongyi_page(
+    vipsite: String,
+    year: String,
+    entid: i32,
+    offset: usize,
+) -> Result<Vec<ShowItem>> {
     let url = format!(
-        "https://api.so.360kan.com/episodeszongyi?site={}&y={}&entid={}&offset={}&count=8&v_ap=1",
-        vipsite, year, entid, offset
+        "https://api.so.360kan.com/episodeszongyi?site={}&y={}&entid={}&offset={}&count={}&v_ap=1",
+        vipsite, year, entid, offset, ZONGYI_PAGE
     );
+    let shows_response: ShowsApiResponse = http::send(Provider::So360, &url).await?.json().await?;
+    Ok(shows_response.data.list)
+}
 
-    let shows_response: ShowsApiResponse = CLIENT
-        .get(&url)
-        .header("User-Agent", "Mozilla/5.0")
-        .send()
-        .await?
-        .json()
-        .await?;
+// 弹幕数量超过阈值时返回的错误，需要用户确认后再下载
+#[derive(Debug)]
+pub struct TooManyComments {
+    pub count: u64,
+    pub url: String,
+}
 
-    let play_url = shows_response
-        .data
-        .list
-        .get(0)
-        .map(|item| item.url.clone())
-        .ok_or_else(|| anyhow!("Cannot find the series"))?;
+impl fmt::Display for TooManyComments {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "about {} comments available", self.count)
+    }
+}
+
+impl error::Error for TooManyComments {}
+
+// 自动匹配的置信度低于 confirm_below 时返回的错误，需要用户确认后再下载
+#[derive(Debug)]
+pub struct LowConfidence {
+    pub title: String,
+    pub episode: usize,
+    pub confidence: f64,
+    pub url: String,
+}
+
+impl fmt::Display for LowConfidence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "matched {} episode {} with confidence {:.2}",
+            self.title, self.episode, self.confidence
+        )
+    }
+}
 
-    Ok(play_url)
+impl error::Error for LowConfidence {}
+
+// 前几个搜索结果与标题的相似度接近时返回的错误，需要用户选择
+#[derive(Debug)]
+pub struct Candidates {
+    pub episode: usize,
+    // 按相似度从高到低排列，第一个为默认使用的结果
+    pub candidates: Vec<LowConfidence>,
 }
 
+impl fmt::Display for Candidates {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} similar results for episode {}",
+            self.candidates.len(),
+            self.episode
+        )
+    }
+}
+
+impl error::Error for Candidates {}
+
 // 获取并处理弹幕数据的函数
 async fn fetch_and_process_danmaku(
     play_url: &str,
     filter: Arc<Filter>,
+    limit: Option<u64>,
 ) -> Result<Vec<Danmaku>> {
+    // 弹幕接口只接收网页地址，不发送本地文件路径
+    if !play_url.starts_with("https://") && !play_url.starts_with("http://") {
+        return Err(anyhow!("not a webpage url: {}", play_url));
+    }
     let danmaku_url = format!("https://danmu.zxz.ee/?type=json&id={}", play_url);
-    let danmaku_response: DanmakuResponse = CLIENT
-        .get(&danmaku_url)
-        .send()
-        .await?
-        .json()
-        .await?;
+    let mut response = http::send(Provider::Zxz, &danmaku_url).await?;
+    // 根据响应长度估算弹幕数量，在读取响应体之前决定是否继续下载
+    if let (Some(limit), Some(len)) = (limit, response.content_length()) {
+        let count = len / AVERAGE_ITEM_SIZE;
+        if count > limit {
+            return Err(TooManyComments {
+                count,
+                url: play_url.into(),
+            }
+            .into());
+        }
+    }
+
+    // 每解析完一块就加入弹幕列表，使开头部分的弹幕可以提前显示
+    let mut stream = ItemStream::default();
+    *COMMENTS.lock().await = Some(Vec::new());
+    while let Some(chunk) = response.chunk().await? {
+        let items = stream.push(&chunk)?;
+        if items.is_empty() {
+            continue;
+        }
+        let items = items.into_iter().map(|item| (item, Kind::Chat)).collect();
+        let mut batch = process_danmaku_response(items, &filter).await;
+        batch.sort_by(|a, b| a.time.total_cmp(&b.time));
+        merge_sorted(COMMENTS.lock().await.get_or_insert_with(Vec::new), batch);
+    }
+    if !stream.finished {
+        return Err(anyhow!("Incomplete danmaku response"));
+    }
+    let mut comments = COMMENTS.lock().await.take().unwrap_or_default();
+    if let Some(window) = filter.merge_window {
+        comments = merge_duplicates(comments, window);
+    }
+    apply_quotas(&mut comments, &filter.quotas);
+    Ok(comments)
+}
 
-    process_danmaku_response(danmaku_response, filter).await
+// 将按时间排序的一批弹幕并入已排序的列表，只移动时间晚于这批弹幕开头的部分
+fn merge_sorted(comments: &mut Vec<Danmaku>, batch: Vec<Danmaku>) {
+    let Some(first) = batch.first() else {
+        return;
+    };
+    let start = comments.partition_point(|c| c.time <= first.time);
+    let mut tail = comments.split_off(start).into_iter().peekable();
+    let mut batch = batch.into_iter().peekable();
+    while let (Some(a), Some(b)) = (tail.peek(), batch.peek()) {
+        let next = if a.time <= b.time {
+            tail.next()
+        } else {
+            batch.next()
+        };
+        comments.extend(next);
+    }
+    comments.extend(tail);
+    comments.extend(batch);
+}
+
+// 配额最紧的来源保留全部弹幕并由此决定总数，其他来源按时间均匀地丢弃超出配额的弹幕
+// 屏蔽的弹幕同样计入，运行时修改屏蔽的来源不影响配额
+fn apply_quotas(comments: &mut Vec<Danmaku>, quotas: &HashMap<Source, f64>) {
+    let mut counts = HashMap::<Source, usize>::new();
+    for comment in comments.iter().filter(|c| quotas.contains_key(&c.source)) {
+        *counts.entry(comment.source).or_default() += 1;
+    }
+    let Some(total) = counts
+        .iter()
+        .map(|(source, &n)| n as f64 / quotas[source])
+        .min_by(f64::total_cmp)
+    else {
+        return;
+    };
+    let mut seen = HashMap::<Source, usize>::new();
+    comments.retain(|comment| {
+        let Some(&n) = counts.get(&comment.source) else {
+            return true;
+        };
+        let keep = ((total * quotas[&comment.source]).round() as usize).min(n);
+        let i = seen.entry(comment.source).or_default();
+        *i += 1;
+        *i * keep / n > (*i - 1) * keep / n
+    });
 }
 
 // 处理弹幕响应的函数
+// 用户字段为 [来源]用户 ID，弹弹play 的用户只有数字 ID
+fn item_source(user: &str) -> (Source, &str) {
+    if user.chars().all(char::is_numeric) {
+        (Source::Dandan, user)
+    } else {
+        user.strip_prefix('[')
+            .and_then(|user| user.split_once(']'))
+            .map(|(source, sender)| (source.into(), sender))
+            .unwrap_or((Source::Unknown, user))
+    }
+}
+
+// 忽略大小写、首尾空白与重复的字符，233333 与 2333 视为相同
+fn duplicate_key(message: &str) -> String {
+    let mut key = String::new();
+    let mut run = (None, 0);
+    for c in message.trim().chars().flat_map(char::to_lowercase) {
+        run = if run.0 == Some(c) {
+            (run.0, run.1 + 1)
+        } else {
+            (Some(c), 1)
+        };
+        if run.1 <= 2 {
+            key.push(c);
+        }
+    }
+    key
+}
+
+// 同一来源、同一类型的相同弹幕从第一条起 window 秒内合并为一条，并标注条数。
+// comments 须已按时间排序
+fn merge_duplicates(comments: Vec<Danmaku>, window: f64) -> Vec<Danmaku> {
+    let mut heads = HashMap::<(Source, Kind, Mode, bool, String), usize>::new();
+    let mut merged = Vec::<(Danmaku, usize)>::with_capacity(comments.len());
+    {
+        let text = text();
+        for comment in comments {
+            let key = (
+                comment.source,
+                comment.kind,
+                comment.mode,
+                comment.blocked,
+                duplicate_key(comment.message.get(&text)),
+            );
+            match heads.get(&key) {
+                Some(&head) if comment.time - merged[head].0.time <= window => merged[head].1 += 1,
+                _ => {
+                    heads.insert(key, merged.len());
+                    merged.push((comment, 1));
+                }
+            }
+        }
+    }
+    merged
+        .into_iter()
+        .map(|(mut comment, n)| {
+            if n > 1 {
+                let message = format!("{} ×{}", comment.message.get(&text()), n);
+                comment.count = message.chars().count();
+                comment.message = Text::new(&message);
+            }
+            comment
+        })
+        .collect()
+}
+
 async fn process_danmaku_response(
-    danmaku_response: DanmakuResponse,
-    filter: Arc<Filter>,
-) -> Result<Vec<Danmaku>> {
+    items: Vec<(DanmakuItem, Kind)>,
+    filter: &Filter,
+) -> Vec<Danmaku> {
     let sources_rt = filter.sources_rt.lock().await;
+    let blocklist = filter.blocklist.lock().await;
 
-    let mut danmaku_list = danmaku_response
-        .danmuku
+    let items = items
         .into_iter()
-        .filter(|item| filter.keywords.iter().all(|pat| !item.3.contains(pat)))
-        .map(|item| {
+        .filter(|(item, _)| {
+            filter.keywords.iter().all(|pat| !item.3.contains(pat))
+                && !filter.patterns.is_match(&item.3)
+        })
+        .collect::<Vec<_>>();
+    items
+        .into_iter()
+        .map(|(item, kind)| {
             let cmessage = item.3;
             let ccount = cmessage.chars().count();
             let color = u32::from_str_radix(&item.2[1..], 16).unwrap_or(0);
-            let user = item.4;
-            let source = if user.chars().all(char::is_numeric) {
-                Source::Dandan
-            } else {
-                user.strip_prefix('[')
-                    .and_then(|user| user.split_once(']').map(|(source, _)| source.into()))
-                    .unwrap_or(Source::Unknown)
-            };
+            let (source, sender) = item_source(&item.4);
             Danmaku {
                 time: item.0,
-                message: cmessage,
+                message: Text::new(&cmessage),
+                sender: Text::new(sender),
                 count: ccount,
                 r: ((color >> 16) & 0xFF) as u8,
                 g: ((color >> 8) & 0xFF) as u8,
                 b: (color & 0xFF) as u8,
                 source,
+                kind,
+                mode: match item.1 {
+                    1 => Mode::Top,
+                    2 => Mode::Bottom,
+                    _ => Mode::Scroll,
+                },
                 blocked: sources_rt
                     .as_ref()
                     .map(|s| s.contains(&source))
-                    .unwrap_or_else(|| filter.sources.contains(&source)),
+                    .unwrap_or_else(|| filter.sources.contains(&source))
+                    || blocklist.contains(sender, &cmessage),
                 status: Status::Uninitialized,
+                new: false,
             }
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
-    danmaku_list.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap());
-    Ok(danmaku_list)
+// bilibili 与弹弹play 的弹幕类型 4 为底部、5 为顶部，转换为 DPlayer 的类型
+fn dplayer_type(mode: u8) -> u8 {
+    match mode {
+        5 => 1,
+        4 => 2,
+        _ => 0,
+    }
+}
+
+// 视频旁同名的 .xml 弹幕文件
+pub fn xml_sidecar(path: &str) -> Option<PathBuf> {
+    Some(Path::new(path).with_extension("xml")).filter(|p| p.is_file())
+}
+
+// XML 中的实体引用
+fn unescape_xml(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('&') {
+        result.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let Some(end) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+// XML 元素的属性，属性之间可以是任意空白，值可以用单引号或双引号
+fn xml_attrs<'a>(regex: &Regex, attrs: &'a str) -> HashMap<&'a str, String> {
+    regex
+        .captures_iter(attrs)
+        .map(|captures| {
+            let value = captures.get(2).or(captures.get(3)).unwrap().as_str();
+            (captures.get(1).unwrap().as_str(), unescape_xml(value))
+        })
+        .collect()
+}
+
+// bilibili 格式的弹幕文件，p 为 时间,类型,字号,颜色,发送时间,弹幕池,用户哈希,弹幕 ID
+// 录播姬等录制直播时保存的弹幕文件另有 gift 与 sc 元素，ts 为在录像中的时间
+fn parse_xml(data: &str) -> Vec<(DanmakuItem, Kind)> {
+    let regex = Regex::new(r#"<(d|gift|sc)(\s[^>]*?)(?:/>|>([^<]*)</(?:d|gift|sc)>)"#).unwrap();
+    let attr_regex = Regex::new(r#"\s([\w:-]+)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    // 时间为 nan 或 inf 的弹幕无法排序
+    let time = |t: &str| t.parse().ok().filter(|t: &f64| t.is_finite());
+    regex
+        .captures_iter(data)
+        .filter_map(|captures| {
+            let mut attrs = xml_attrs(&attr_regex, &captures[2]);
+            let text = captures
+                .get(3)
+                .map_or(String::new(), |m| unescape_xml(m.as_str()));
+            let user = attrs.remove("user").unwrap_or_default();
+            match &captures[1] {
+                "d" => {
+                    let p = attrs.remove("p")?;
+                    let mut p = p.split(',');
+                    let time = time(p.next()?)?;
+                    // 7 为高级弹幕，8、9 为代码弹幕，内容是 JSON 或脚本而非文字
+                    let mode = p.next()?.parse().ok().filter(|m| !(7..=9).contains(m))?;
+                    let color = p.nth(1)?.parse::<u32>().ok()?;
+                    let hash = p.nth(2).unwrap_or_default();
+                    Some((
+                        DanmakuItem(
+                            time,
+                            dplayer_type(mode),
+                            format!("#{:06X}", color),
+                            text,
+                            format!("[bilibili]{}", hash),
+                        ),
+                        Kind::Chat,
+                    ))
+                }
+                "gift" => Some((
+                    DanmakuItem(
+                        time(&attrs.remove("ts")?)?,
+                        0,
+                        "#FFFFFF".into(),
+                        format!(
+                            "{} 送出{} ×{}",
+                            user,
+                            attrs.remove("giftname").unwrap_or("礼物".into()),
+                            attrs.remove("giftcount").unwrap_or("1".into())
+                        ),
+                        format!("[bilibili]{}", user),
+                    ),
+                    Kind::Gift,
+                )),
+                _ => Some((
+                    DanmakuItem(
+                        time(&attrs.remove("ts")?)?,
+                        0,
+                        "#FFFFFF".into(),
+                        format!("{}：{}", user, text),
+                        format!("[bilibili]{}", user),
+                    ),
+                    Kind::Paid,
+                )),
+            }
+        })
+        .collect()
+}
+
+pub async fn load_xml(path: &Path, filter: &Filter) -> Result<Vec<Danmaku>> {
+    let mut data = String::new();
+    File::open(path)?.read_to_string(&mut data)?;
+    let items = parse_xml(&data);
+    if items.is_empty() {
+        return Err(anyhow!("no danmaku in {}", path.display()));
+    }
+    // 与直播弹幕一样可以用 filter_gift 等选项隐藏
+    let items = items
+        .into_iter()
+        .filter(|(_, kind)| !filter.kinds.contains(kind))
+        .collect();
+    let mut comments = process_danmaku_response(items, filter).await;
+    comments.sort_by(|a, b| a.time.total_cmp(&b.time));
+    if let Some(window) = filter.merge_window {
+        comments = merge_duplicates(comments, window);
+    }
+    apply_quotas(&mut comments, &filter.quotas);
+    Ok(comments)
+}
+
+// 文件前 16MB 的 MD5 与文件大小，与弹弹play 的算法一致
+fn file_hash(path: &Path) -> Result<(String, u64)> {
+    let file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut data = Vec::new();
+    file.take(HASH_SIZE).read_to_end(&mut data)?;
+    Ok((hex::encode(Md5::digest(&data)), size))
+}
+
+// 通过文件哈希在弹弹play 匹配本地文件，没有唯一匹配时返回 None
+pub async fn get_danmaku_byhash(
+    path: &str,
+    anonymize: bool,
+    filter: Arc<Filter>,
+    limit: Option<u64>,
+) -> Result<Option<Vec<Danmaku>>> {
+    let path = Path::new(path);
+    // 读取 16MB 可能较慢，不阻塞异步任务
+    let (hash, size) = {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || file_hash(&path)).await??
+    };
+    // anonymize_query 时只发送哈希与大小
+    let body = if anonymize {
+        json!({ "fileName": "", "fileHash": hash, "fileSize": size, "matchMode": "hashOnly" })
+    } else {
+        json!({
+            "fileName": path.file_stem().map(|s| s.to_string_lossy()).unwrap_or_default(),
+            "fileHash": hash,
+            "fileSize": size,
+            "videoDuration": get_property_f64(c"duration").map_or(0, |d| d as u64),
+            "matchMode": "hashAndFileName",
+        })
+    };
+    let response: MatchResponse = http::post(Provider::Dandanplay, DANDANPLAY_MATCH, &body)
+        .await?
+        .json()
+        .await?;
+    let Some(matched) = response.matches.first().filter(|_| response.is_matched) else {
+        return Ok(None);
+    };
+    log_info(&format!(
+        "dandanplay: matched {} {}",
+        matched.anime_title, matched.episode_title
+    ));
+    set_matched(Some(Matched {
+        platform: "dandanplay".into(),
+        episode: Some(matched.episode_title.clone()),
+    }));
+    let url = format!(
+        "{}{}?withRelated=true",
+        DANDANPLAY_COMMENT, matched.episode_id
+    );
+    fetch_dandanplay_comments(&url, filter, limit)
+        .await
+        .map(Some)
+}
+
+// 弹弹play 的弹幕接口，withRelated 时包含其他网站的弹幕，用户字段同样为 [来源]用户 ID
+async fn fetch_dandanplay_comments(
+    url: &str,
+    filter: Arc<Filter>,
+    limit: Option<u64>,
+) -> Result<Vec<Danmaku>> {
+    let response = http::send(Provider::Dandanplay, url).await?;
+    if let (Some(limit), Some(len)) = (limit, response.content_length()) {
+        let count = len / AVERAGE_ITEM_SIZE;
+        if count > limit {
+            return Err(TooManyComments {
+                count,
+                url: url.into(),
+            }
+            .into());
+        }
+    }
+    let items = response
+        .json::<CommentResponse>()
+        .await?
+        .comments
+        .into_iter()
+        .filter_map(|comment| {
+            let mut p = comment.p.split(',');
+            let time = p.next()?.parse().ok().filter(|t: &f64| t.is_finite())?;
+            let mode = p.next()?.parse().ok()?;
+            let color = p.next()?.parse::<u32>().ok()?;
+            let user = p.next().unwrap_or_default().to_string();
+            Some((
+                DanmakuItem(
+                    time,
+                    dplayer_type(mode),
+                    format!("#{:06X}", color),
+                    comment.m,
+                    user,
+                ),
+                Kind::Chat,
+            ))
+        })
+        .collect();
+    let mut comments = process_danmaku_response(items, &filter).await;
+    comments.sort_by(|a, b| a.time.total_cmp(&b.time));
+    if let Some(window) = filter.merge_window {
+        comments = merge_duplicates(comments, window);
+    }
+    apply_quotas(&mut comments, &filter.quotas);
+    Ok(comments)
 }
 
 // 重构后的 get_danmaku 函数
-pub async fn get_danmaku(name: &str, filter: Arc<Filter>) -> Result<Vec<Danmaku>> {
-    let query = parse_name(name)?;
+pub async fn get_danmaku(
+    name: &str,
+    anonymize: bool,
+    confirm_below: f64,
+    filter: Arc<Filter>,
+    limit: Option<u64>,
+) -> Result<Vec<Danmaku>> {
+    let query = parse_query(name, anonymize)?;
     let episode_number = query.episode_number.unwrap_or(1);
     let search_url = construct_search_url(&query);
 
-    let search_response: SearchResponse = CLIENT
-        .get(&search_url)
-        .header("User-Agent", "Mozilla/5.0")
-        .send()
+    let search_response: SearchResponse = http::send(Provider::So360, &search_url)
         .await?
         .json()
         .await?;
 
-    let play_url = extract_play_url(&search_response, episode_number).await?;
-    fetch_and_process_danmaku(&play_url, filter).await
+    let series = load_series(&query.title)
+        .map_err(|error| log_error(&anyhow!("series {}: {}", query.title, error)))
+        .ok()
+        .flatten();
+    let platform = series.as_ref().and_then(|s| s.platform.clone());
+    set_style(series.map(|s| s.style));
+    let rows = search_rows(&search_response)?;
+    let (play_url, title) =
+        extract_play_url(&rows[0], &query, &filter.platforms, platform.as_deref()).await?;
+    set_matched(Some(Matched {
+        episode: query.air_date.clone().or_else(|| {
+            query
+                .episode_number
+                .map(|episode| format!("EP{:02}", episode))
+        }),
+        ..Matched::from_url(&play_url)
+    }));
+    let matched = LowConfidence {
+        confidence: confidence(&query, title.as_deref()),
+        title: title.unwrap_or_else(|| query.title.clone()),
+        episode: episode_number,
+        url: play_url,
+    };
+    log_info(&matched.to_string());
+    // 之后几行的相似度与第一行接近时列出供选择，无法提取播放链接的行不列出
+    let mut candidates = Vec::new();
+    for row in rows.iter().skip(1).take(MAX_CANDIDATES - 1) {
+        let confidence = confidence(&query, row_title(row).as_deref());
+        if matched.confidence - confidence > CLOSE_CONFIDENCE {
+            continue;
+        }
+        match extract_play_url(row, &query, &filter.platforms, platform.as_deref()).await {
+            Ok((url, title)) => candidates.push(LowConfidence {
+                title: title.unwrap_or_default(),
+                episode: episode_number,
+                confidence,
+                url,
+            }),
+            Err(error) => log_debug(&format!("candidate: {}", error)),
+        }
+    }
+    if !candidates.is_empty() {
+        candidates.insert(0, matched);
+        candidates.sort_by(|a, b| b.confidence.partial_cmp(&a.confidence).unwrap());
+        return Err(Candidates {
+            episode: episode_number,
+            candidates,
+        }
+        .into());
+    }
+    if matched.confidence < confirm_below {
+        return Err(matched.into());
+    }
+    let play_url = matched.url;
+    fetch_and_process_danmaku(&play_url, filter, limit).await
+}
+
+// 弹幕接口可直接通过网页地址获取弹幕的网站，对应 yt-dlp 的 extractor 名称
+pub fn supports_extractor(extractor: &str) -> bool {
+    let extractor = extractor.to_ascii_lowercase();
+    ["bilibili", "iqiyi", "youku", "vqq", "mangotv"]
+        .iter()
+        .any(|site| extractor.starts_with(site))
 }
 
 // 重构后的 get_danmaku_byurl 函数
-pub async fn get_danmaku_byurl(url: &str, filter: Arc<Filter>) -> Result<Vec<Danmaku>> {
-    fetch_and_process_danmaku(url, filter).await
-}
\ No newline at end of file
+pub async fn get_danmaku_byurl(
+    url: &str,
+    filter: Arc<Filter>,
+    limit: Option<u64>,
+) -> Result<Vec<Danmaku>> {
+    // 确认弹幕数量后继续下载弹弹play 的弹幕
+    if url.starts_with(DANDANPLAY_COMMENT) {
+        return fetch_dandanplay_comments(url, filter, limit).await;
+    }
+    fetch_and_process_danmaku(url, filter, limit).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(time: f64) -> Danmaku {
+        Danmaku {
+            message: Text::new(""),
+            sender: Text::new(""),
+            count: 0,
+            time,
+            r: 0xFF,
+            g: 0xFF,
+            b: 0xFF,
+            source: Source::Unknown,
+            kind: Kind::Chat,
+            mode: Mode::Scroll,
+            blocked: false,
+            status: Status::Uninitialized,
+            new: false,
+        }
+    }
+
+    #[test]
+    fn merge_chunks() {
+        let mut comments = Vec::new();
+        for chunk in [&[3., 1., 2.][..], &[5., 4.], &[2.5, 6., 0.5], &[]] {
+            let mut batch = chunk.iter().map(|&t| comment(t)).collect::<Vec<_>>();
+            batch.sort_by(|a, b| a.time.total_cmp(&b.time));
+            merge_sorted(&mut comments, batch);
+        }
+        let times = comments.iter().map(|c| c.time).collect::<Vec<_>>();
+        assert_eq!(times, [0.5, 1., 2., 2.5, 3., 4., 5., 6.]);
+    }
+
+    #[test]
+    fn merge_across_chunks() {
+        let mut comments = Vec::new();
+        for chunk in [
+            &[(1., "2333"), (2., "好")][..],
+            &[(3., "233333"), (12., "2333")],
+        ] {
+            let batch = chunk
+                .iter()
+                .map(|&(t, m)| Danmaku {
+                    message: Text::new(m),
+                    count: m.chars().count(),
+                    ..comment(t)
+                })
+                .collect();
+            merge_sorted(&mut comments, batch);
+        }
+        let comments = merge_duplicates(comments, 5.);
+        let text = text();
+        let merged = comments
+            .iter()
+            .map(|c| (c.time, c.message.get(&text), c.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            merged,
+            [(1., "2333 ×2", 7), (2., "好", 1), (12., "2333", 4)]
+        );
+    }
+
+    #[test]
+    fn xml() {
+        let items = parse_xml(include_str!("../tests/fixtures/danmaku.xml"))
+            .into_iter()
+            .map(|(item, kind)| (item.0, item.1, item.2, item.3, item.4, kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            items,
+            [
+                (
+                    1.5,
+                    0,
+                    "#FFFFFF".into(),
+                    "第一条".into(),
+                    "[bilibili]a1b2c3d4".into(),
+                    Kind::Chat
+                ),
+                (
+                    2.25,
+                    1,
+                    "#FF0000".into(),
+                    "顶部 & <留言>".into(),
+                    "[bilibili]e5f6a7b8".into(),
+                    Kind::Chat
+                ),
+                (
+                    3.,
+                    2,
+                    "#00FF00".into(),
+                    "底部".into(),
+                    "[bilibili]".into(),
+                    Kind::Chat
+                ),
+                (
+                    4.5,
+                    0,
+                    "#FFFFFF".into(),
+                    "观众 送出小心心 ×3".into(),
+                    "[bilibili]观众".into(),
+                    Kind::Gift
+                ),
+                (
+                    6.,
+                    0,
+                    "#FFFFFF".into(),
+                    "观众：醒目留言".into(),
+                    "[bilibili]观众".into(),
+                    Kind::Paid
+                ),
+                (
+                    7.,
+                    0,
+                    "#FFFFFF".into(),
+                    "路人 送出辣条 ×10".into(),
+                    "[bilibili]路人".into(),
+                    Kind::Gift
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,50 @@
+use crate::danmaku::{Danmaku, Kind, Mode, Source, Status, Text};
+use rand::{seq::SliceRandom, thread_rng, Rng};
+
+const CHARS: &[char] = &[
+    '哈', '啊', '草', '好', '看', '前', '方', '高', '能', '弹', '幕', '测', '试', '！', '？', '6',
+    'w',
+];
+// 多数弹幕为白色
+const COLORS: &[u32] = &[
+    0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0xFFFFFF, 0xFE0302, 0xFFFF00, 0x00CD00, 0x4266BE, 0xCC0273,
+];
+
+// 在 [start, start + duration) 内随机生成弹幕，约三成集中在几个爆发点之后的两秒内
+pub fn generate(count: usize, start: f64, duration: f64) -> Vec<Danmaku> {
+    let mut rng = thread_rng();
+    let bursts = (0..(duration / 30.).ceil() as usize)
+        .map(|_| start + rng.gen_range(0. ..duration))
+        .collect::<Vec<_>>();
+    let mut comments = (0..count)
+        .map(|_| {
+            let time = if rng.gen_bool(0.3) {
+                (bursts.choose(&mut rng).unwrap() + rng.gen_range(0. ..2.)).min(start + duration)
+            } else {
+                start + rng.gen_range(0. ..duration)
+            };
+            let max_len = if rng.gen_bool(0.1) { 40 } else { 12 };
+            let message = (0..rng.gen_range(1..=max_len))
+                .map(|_| *CHARS.choose(&mut rng).unwrap())
+                .collect::<String>();
+            let color = *COLORS.choose(&mut rng).unwrap();
+            Danmaku {
+                message: Text::new(&message),
+                sender: Text::new(""),
+                count: message.chars().count(),
+                time,
+                r: ((color >> 16) & 0xFF) as u8,
+                g: ((color >> 8) & 0xFF) as u8,
+                b: (color & 0xFF) as u8,
+                source: Source::Unknown,
+                kind: Kind::Chat,
+                mode: Mode::Scroll,
+                blocked: false,
+                status: Status::Uninitialized,
+                new: false,
+            }
+        })
+        .collect::<Vec<_>>();
+    comments.sort_by(|a, b| a.time.total_cmp(&b.time));
+    comments
+}
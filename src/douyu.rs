@@ -0,0 +1,206 @@
+use crate::{
+    danmaku::{Kind, Source},
+    http::{self, Provider},
+    live::{LiveMessage, Protocol},
+};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{collections::HashMap, time::Duration};
+use tokio_tungstenite::tungstenite::Message;
+
+const CLIENT_TYPE: u16 = 689;
+
+#[derive(Deserialize)]
+struct Betard {
+    room: BetardRoom,
+}
+
+#[derive(Deserialize)]
+struct BetardRoom {
+    room_id: u64,
+}
+
+pub struct Douyu {
+    room: String,
+    room_id: u64,
+}
+
+impl Douyu {
+    // 支持 https://www.douyu.com/288016 及 https://www.douyu.com/topic/xxx?rid=288016
+    pub fn detect(url: &str) -> Option<Self> {
+        let (_, rest) = url.split_once("douyu.com/")?;
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let room = query
+            .split('&')
+            .find_map(|q| q.strip_prefix("rid="))
+            .or_else(|| path.split('/').rfind(|s| !s.is_empty()))?;
+        Some(Self {
+            room: room.to_string(),
+            room_id: 0,
+        })
+    }
+}
+
+// 斗鱼 STT 序列化格式：key@=value/，其中 / 与 @ 分别转义为 @S 与 @A
+fn escape(value: &str) -> String {
+    value.replace('@', "@A").replace('/', "@S")
+}
+
+fn unescape(value: &str) -> String {
+    value.replace("@S", "/").replace("@A", "@")
+}
+
+fn encode(fields: &[(&str, &str)]) -> Message {
+    let mut body = fields
+        .iter()
+        .map(|(k, v)| format!("{}@={}/", escape(k), escape(v)))
+        .collect::<String>()
+        .into_bytes();
+    body.push(0);
+    let len = (body.len() + 8) as u32;
+    let mut packet = Vec::with_capacity(body.len() + 12);
+    packet.extend(len.to_le_bytes());
+    packet.extend(len.to_le_bytes());
+    packet.extend(CLIENT_TYPE.to_le_bytes());
+    packet.extend([0, 0]);
+    packet.extend(body);
+    Message::binary(packet)
+}
+
+fn parse(body: &str) -> HashMap<String, String> {
+    body.split('/')
+        .filter_map(|field| field.split_once("@="))
+        .map(|(k, v)| (unescape(k), unescape(v)))
+        .collect()
+}
+
+fn color(col: Option<&str>) -> u32 {
+    match col {
+        Some("1") => 0xFF0000,
+        Some("2") => 0x1E87F0,
+        Some("3") => 0x7AC84B,
+        Some("4") => 0xFF7F00,
+        Some("5") => 0x9B39F4,
+        Some("6") => 0xFF69B4,
+        _ => 0xFFFFFF,
+    }
+}
+
+impl Protocol for Douyu {
+    const SOURCE: Source = Source::Douyu;
+    const PROVIDER: Provider = Provider::Douyu;
+    const HEARTBEAT: Duration = Duration::from_secs(45);
+
+    async fn prepare(&mut self) -> Result<String> {
+        if self.room_id == 0 {
+            self.room_id = match self.room.parse() {
+                Ok(room_id) => room_id,
+                Err(_) => {
+                    let betard: Betard = http::send(
+                        Provider::Douyu,
+                        &format!("https://www.douyu.com/betard/{}", self.room),
+                    )
+                    .await?
+                    .json()
+                    .await?;
+                    betard.room.room_id
+                }
+            };
+        }
+        Ok("wss://danmuproxy.douyu.com:8506/".into())
+    }
+
+    fn handshake(&self) -> Vec<Message> {
+        let room_id = self.room_id.to_string();
+        vec![
+            encode(&[("type", "loginreq"), ("roomid", &room_id)]),
+            encode(&[("type", "joingroup"), ("rid", &room_id), ("gid", "-9999")]),
+        ]
+    }
+
+    fn heartbeat(&self) -> Message {
+        encode(&[("type", "mrkl")])
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<LiveMessage>> {
+        let data = message.into_data();
+        let mut messages = Vec::new();
+        let mut rest = &data[..];
+        // 一帧中可能包含多个数据包
+        while rest.len() >= 12 {
+            let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            let packet = rest
+                .get(4..4 + len)
+                .filter(|p| p.len() >= 8)
+                .ok_or_else(|| anyhow!("douyu: truncated packet"))?;
+            rest = &rest[4 + len..];
+            let body = String::from_utf8_lossy(&packet[8..]);
+            let fields = parse(body.trim_end_matches('\0'));
+            let field = |k: &str| fields.get(k).map(String::as_str).unwrap_or_default();
+            let (message, color, kind) = match field("type") {
+                "chatmsg" => (
+                    field("txt").to_string(),
+                    color(fields.get("col").map(String::as_str)),
+                    Kind::Chat,
+                ),
+                "dgb" | "spbc" => (
+                    format!("{} 送出礼物 ×{}", field("nn"), field("gfcnt")),
+                    0xFFFFFF,
+                    Kind::Gift,
+                ),
+                // 房间用户抢红包，snk 为抢到的用户，dnk 为红包的主人
+                "ggbb" => (
+                    format!("{} 抢到了 {} 的红包", field("snk"), field("dnk")),
+                    0xFFFFFF,
+                    Kind::Lottery,
+                ),
+                "uenter" => (
+                    format!("{} 进入了直播间", field("nn")),
+                    0xFFFFFF,
+                    Kind::Enter,
+                ),
+                _ => continue,
+            };
+            if !message.is_empty() {
+                messages.push(LiveMessage {
+                    message,
+                    color,
+                    kind,
+                });
+            }
+        }
+        Ok(messages)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(fields: &[(&str, &str)]) -> Vec<(String, Kind)> {
+        let mut douyu = Douyu::detect("https://www.douyu.com/1").unwrap();
+        douyu
+            .decode(encode(fields))
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.message, m.kind))
+            .collect()
+    }
+
+    #[test]
+    fn lottery() {
+        assert_eq!(
+            decode(&[("type", "chatmsg"), ("nn", "观众"), ("txt", "弹幕")]),
+            [("弹幕".into(), Kind::Chat)]
+        );
+        assert_eq!(
+            decode(&[
+                ("type", "ggbb"),
+                ("snk", "观众"),
+                ("dnk", "主播"),
+                ("sl", "10")
+            ]),
+            [("观众 抢到了 主播 的红包".into(), Kind::Lottery)]
+        );
+    }
+}
@@ -2,7 +2,7 @@
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 
-use std::os::raw::{c_char, c_int, c_void};
+use std::os::raw::{c_char, c_int, c_ulong, c_void};
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -137,6 +137,7 @@ pub struct mpv_event {
 
 #[cfg(not(target_os = "windows"))]
 extern "C" {
+    pub fn mpv_client_api_version() -> c_ulong;
     pub fn mpv_error_string(error: c_int) -> *const c_char;
     pub fn mpv_free(data: *mut c_void);
     pub fn mpv_client_name(ctx: *mut mpv_handle) -> *const c_char;
@@ -158,6 +159,12 @@ extern "C" {
         format: mpv_format,
         data: *mut c_void,
     ) -> c_int;
+    pub fn mpv_set_property(
+        ctx: *mut mpv_handle,
+        name: *const c_char,
+        format: mpv_format,
+        data: *mut c_void,
+    ) -> c_int;
     pub fn mpv_observe_property(
         ctx: *mut mpv_handle,
         reply_userdata: u64,
@@ -169,6 +176,9 @@ extern "C" {
     pub fn mpv_wakeup(ctx: *mut mpv_handle);
 }
 
+#[cfg(target_os = "windows")]
+#[no_mangle]
+static mut pfn_mpv_client_api_version: Option<extern "C" fn() -> c_ulong> = None;
 #[cfg(target_os = "windows")]
 #[no_mangle]
 static mut pfn_mpv_error_string: Option<extern "C" fn(error: c_int) -> *const c_char> = None;
@@ -208,6 +218,16 @@ static mut pfn_mpv_get_property: Option<
 > = None;
 #[cfg(target_os = "windows")]
 #[no_mangle]
+static mut pfn_mpv_set_property: Option<
+    extern "C" fn(
+        ctx: *mut mpv_handle,
+        name: *const c_char,
+        format: mpv_format,
+        data: *mut c_void,
+    ) -> c_int,
+> = None;
+#[cfg(target_os = "windows")]
+#[no_mangle]
 static mut pfn_mpv_observe_property: Option<
     extern "C" fn(
         ctx: *mut mpv_handle,
@@ -228,6 +248,10 @@ pub static mut pfn_mpv_wait_event: Option<
 #[no_mangle]
 pub static mut pfn_mpv_wakeup: Option<extern "C" fn(ctx: *mut mpv_handle)> = None;
 
+#[cfg(target_os = "windows")]
+pub unsafe fn mpv_client_api_version() -> c_ulong {
+    pfn_mpv_client_api_version.unwrap()()
+}
 #[cfg(target_os = "windows")]
 pub unsafe fn mpv_error_string(error: c_int) -> *const c_char {
     pfn_mpv_error_string.unwrap()(error)
@@ -274,6 +298,15 @@ pub unsafe fn mpv_get_property(
     pfn_mpv_get_property.unwrap()(ctx, name, format, data)
 }
 #[cfg(target_os = "windows")]
+pub unsafe fn mpv_set_property(
+    ctx: *mut mpv_handle,
+    name: *const c_char,
+    format: mpv_format,
+    data: *mut c_void,
+) -> c_int {
+    pfn_mpv_set_property.unwrap()(ctx, name, format, data)
+}
+#[cfg(target_os = "windows")]
 pub unsafe fn mpv_observe_property(
     ctx: *mut mpv_handle,
     reply_userdata: u64,
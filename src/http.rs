@@ -0,0 +1,386 @@
+use crate::log::log_debug;
+#[cfg(feature = "network")]
+use crate::log::log_error;
+use anyhow::{anyhow, Result};
+use md5::{Digest, Md5};
+#[cfg(feature = "network")]
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, ORIGIN, REFERER, USER_AGENT},
+    Client, RequestBuilder,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+#[cfg(feature = "network")]
+use std::{
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinSet;
+
+// 定义全局的 HTTP 客户端
+#[cfg(feature = "network")]
+pub static CLIENT: LazyLock<Client> = LazyLock::new(Client::new);
+
+static HEADERS: OnceLock<HashMap<Provider, Headers>> = OnceLock::new();
+static FIXTURES: OnceLock<Fixtures> = OnceLock::new();
+// 同时发出的请求数上限，未设置时为 DEFAULT_REQUESTS
+static MAX_REQUESTS: OnceLock<usize> = OnceLock::new();
+const DEFAULT_REQUESTS: usize = 4;
+// 所有下载共用的速率上限，字节每秒
+static MAX_BANDWIDTH: OnceLock<u64> = OnceLock::new();
+// 按速率上限已读取的数据读完的时刻，之前不再读取下一块
+#[cfg(feature = "network")]
+static THROTTLE: Mutex<Option<Instant>> = Mutex::new(None);
+
+// 各服务收到的内容，均不包含本地文件路径
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum Provider {
+    // 由 media-title 解析出的标题与季数，以及匹配到的剧集 ID、年份与集数偏移
+    So360,
+    // 匹配到的或 yt-dlp 提供的视频网页地址
+    Zxz,
+    // 本地文件前 16MB 的 MD5、文件大小、不含路径与扩展名的文件名与时长，匹配到的剧集 ID
+    Dandanplay,
+    // 播放地址中的直播间号
+    Bilibili,
+    Douyu,
+    Huya,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 6] = [
+        Provider::So360,
+        Provider::Zxz,
+        Provider::Dandanplay,
+        Provider::Bilibili,
+        Provider::Douyu,
+        Provider::Huya,
+    ];
+
+    // 用于选项名后缀，如 user_agent_360kan
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::So360 => "360kan",
+            Provider::Zxz => "zxz",
+            Provider::Dandanplay => "dandanplay",
+            Provider::Bilibili => "bilibili",
+            Provider::Douyu => "douyu",
+            Provider::Huya => "huya",
+        }
+    }
+
+    // 用于连通性检查的地址
+    #[cfg(feature = "network")]
+    fn check_url(self) -> &'static str {
+        match self {
+            Provider::So360 => "https://api.so.360kan.com/",
+            Provider::Zxz => "https://danmu.zxz.ee/",
+            Provider::Dandanplay => "https://api.dandanplay.net/",
+            Provider::Bilibili => "https://api.live.bilibili.com/",
+            Provider::Douyu => "https://www.douyu.com/",
+            Provider::Huya => "https://www.huya.com/",
+        }
+    }
+
+    #[cfg(feature = "network")]
+    fn default_headers(self) -> Headers {
+        match self {
+            Provider::So360 | Provider::Bilibili | Provider::Douyu | Provider::Huya => Headers {
+                user_agent: Some("Mozilla/5.0".into()),
+                ..Default::default()
+            },
+            Provider::Zxz | Provider::Dandanplay => Headers::default(),
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct Headers {
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    pub origin: Option<String>,
+    // 弹弹play 开放平台的应用 ID 与密钥
+    pub app_id: Option<String>,
+    pub app_secret: Option<String>,
+}
+
+pub fn set_headers(headers: HashMap<Provider, Headers>) {
+    _ = HEADERS.set(headers);
+}
+
+// 弹弹play 的接口需要应用 ID 与密钥，未设置时不发送任何请求
+pub fn dandanplay_credentials() -> bool {
+    HEADERS
+        .get()
+        .and_then(|h| h.get(&Provider::Dandanplay))
+        .is_some_and(|h| h.app_id.is_some() && h.app_secret.is_some())
+}
+
+// 选项中未设置的请求头使用默认值
+#[cfg(feature = "network")]
+pub fn headers(provider: Provider) -> HeaderMap {
+    let default = provider.default_headers();
+    let config = HEADERS.get().and_then(|h| h.get(&provider));
+    let mut headers = HeaderMap::new();
+    for (name, value, default) in [
+        (
+            USER_AGENT,
+            config.and_then(|c| c.user_agent.as_ref()),
+            default.user_agent.as_ref(),
+        ),
+        (
+            REFERER,
+            config.and_then(|c| c.referer.as_ref()),
+            default.referer.as_ref(),
+        ),
+        (
+            ORIGIN,
+            config.and_then(|c| c.origin.as_ref()),
+            default.origin.as_ref(),
+        ),
+        (
+            HeaderName::from_static("x-appid"),
+            config.and_then(|c| c.app_id.as_ref()),
+            default.app_id.as_ref(),
+        ),
+        (
+            HeaderName::from_static("x-appsecret"),
+            config.and_then(|c| c.app_secret.as_ref()),
+            default.app_secret.as_ref(),
+        ),
+    ] {
+        if let Some(value) = value
+            .or(default)
+            .and_then(|v| HeaderValue::from_str(v).ok())
+        {
+            headers.insert(name, value);
+        }
+    }
+    headers
+}
+
+#[cfg(feature = "network")]
+pub fn get(provider: Provider, url: &str) -> RequestBuilder {
+    CLIENT.get(url).headers(headers(provider))
+}
+
+// 用于复现问题：将各服务的响应保存到目录中，或从目录中读取而不访问网络
+pub enum Fixtures {
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+pub fn set_fixtures(fixtures: Fixtures) {
+    _ = FIXTURES.set(fixtures);
+}
+
+pub fn set_max_requests(n: usize) {
+    _ = MAX_REQUESTS.set(n);
+}
+
+pub fn max_requests() -> usize {
+    MAX_REQUESTS.get().copied().unwrap_or(DEFAULT_REQUESTS)
+}
+
+// 同时最多执行 max_requests 个请求，结果按输入的顺序返回
+pub async fn fetch_all<T, F>(requests: impl IntoIterator<Item = F>) -> Vec<Result<T>>
+where
+    T: Send + 'static,
+    F: Future<Output = Result<T>> + Send + 'static,
+{
+    let mut set = JoinSet::new();
+    // 任务 panic 时没有结果，对应位置保持为 None
+    let mut results = Vec::<Option<Result<T>>>::new();
+    for (i, request) in requests.into_iter().enumerate() {
+        if set.len() >= max_requests() {
+            if let Some(Ok((i, result))) = set.join_next().await {
+                results[i] = Some(result);
+            }
+        }
+        results.push(None);
+        set.spawn(async move { (i, request.await) });
+    }
+    while let Some(result) = set.join_next().await {
+        if let Ok((i, result)) = result {
+            results[i] = Some(result);
+        }
+    }
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(anyhow!("request task failed"))))
+        .collect()
+}
+
+pub fn set_max_bandwidth(bytes: u64) {
+    _ = MAX_BANDWIDTH.set(bytes);
+}
+
+// 暂停读取响应体时 TCP 窗口随之收缩，与同一连接上的视频流共享带宽
+#[cfg(feature = "network")]
+async fn throttle(len: usize) {
+    let Some(&rate) = MAX_BANDWIDTH.get() else {
+        return;
+    };
+    let until = {
+        let mut next = THROTTLE.lock().unwrap();
+        let now = Instant::now();
+        let until = next.map_or(now, |next| next.max(now))
+            + Duration::from_secs_f64(len as f64 / rate as f64);
+        *next = Some(until);
+        until
+    };
+    tokio::time::sleep_until(until.into()).await;
+}
+
+fn fixture_path(dir: &Path, provider: Provider, url: &str) -> PathBuf {
+    dir.join(format!(
+        "{}-{}.body",
+        provider.name(),
+        hex::encode(Md5::digest(url))
+    ))
+}
+
+pub enum Response {
+    // 录制时保存文件路径与已读取的响应体
+    #[cfg(feature = "network")]
+    Remote(reqwest::Response, Option<(PathBuf, Vec<u8>)>),
+    Replay(Option<Vec<u8>>),
+}
+
+impl Response {
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            #[cfg(feature = "network")]
+            Response::Remote(response, _) => response.content_length(),
+            Response::Replay(body) => body.as_ref().map(|body| body.len() as u64),
+        }
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>> {
+        match self {
+            #[cfg(feature = "network")]
+            Response::Remote(response, record) => {
+                let chunk = response.chunk().await?;
+                if let Some(chunk) = &chunk {
+                    throttle(chunk.len()).await;
+                }
+                if let Some((path, body)) = record {
+                    match &chunk {
+                        Some(chunk) => body.extend_from_slice(chunk),
+                        None => {
+                            if let Err(error) = path
+                                .parent()
+                                .map_or(Ok(()), fs::create_dir_all)
+                                .and_then(|_| fs::write(&path, &body))
+                            {
+                                log_error(&anyhow!("record {}: {}", path.display(), error));
+                            }
+                        }
+                    }
+                }
+                Ok(chunk.map(|chunk| chunk.to_vec()))
+            }
+            Response::Replay(body) => Ok(body.take()),
+        }
+    }
+
+    pub async fn json<T: DeserializeOwned>(mut self) -> Result<T> {
+        let mut body = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            body.extend(chunk);
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+pub async fn send(provider: Provider, url: &str) -> Result<Response> {
+    request(provider, url, None).await
+}
+
+// 以 JSON 为请求体的 POST 请求，录制与回放时以地址与请求体区分
+pub async fn post(provider: Provider, url: &str, body: &Value) -> Result<Response> {
+    request(provider, url, Some(body)).await
+}
+
+async fn request(provider: Provider, url: &str, body: Option<&Value>) -> Result<Response> {
+    let key = match body {
+        Some(body) => {
+            log_debug(&format!("http {}: POST {} {}", provider.name(), url, body));
+            format!("{} {}", url, body)
+        }
+        None => {
+            log_debug(&format!("http {}: GET {}", provider.name(), url));
+            url.to_string()
+        }
+    };
+    match FIXTURES.get() {
+        Some(Fixtures::Replay(dir)) => {
+            let path = fixture_path(dir, provider, &key);
+            let body = fs::read(&path)
+                .map_err(|e| anyhow!("replay {} ({}): {}", url, path.display(), e))?;
+            Ok(Response::Replay(Some(body)))
+        }
+        #[cfg(not(feature = "network"))]
+        _ => Err(anyhow!("{}: network access is not compiled in", url)),
+        #[cfg(feature = "network")]
+        fixtures => {
+            let response = match body {
+                Some(body) => CLIENT.post(url).headers(headers(provider)).json(body),
+                None => get(provider, url),
+            }
+            .send()
+            .await?;
+            let record = match fixtures {
+                Some(Fixtures::Record(dir)) => {
+                    Some((fixture_path(dir, provider, &key), Vec::new()))
+                }
+                _ => None,
+            };
+            Ok(Response::Remote(response, record))
+        }
+    }
+}
+
+// 检查各服务的连通性，返回每个服务的状态与延迟
+#[cfg(feature = "network")]
+pub async fn check_providers() -> Vec<(Provider, String)> {
+    let handles = Provider::ALL.map(|provider| {
+        tokio::spawn(async move {
+            let start = Instant::now();
+            let result = get(provider, provider.check_url())
+                .timeout(Duration::from_secs(5))
+                .send()
+                .await;
+            match result {
+                Ok(response) => {
+                    format!("{}, {} ms", response.status(), start.elapsed().as_millis())
+                }
+                Err(error) if error.is_timeout() => "timed out".into(),
+                Err(error) => format!("unreachable ({})", error),
+            }
+        })
+    });
+    let mut status = Vec::new();
+    for (provider, handle) in Provider::ALL.into_iter().zip(handles) {
+        status.push((provider, handle.await.unwrap_or_else(|e| e.to_string())));
+    }
+    status
+}
+
+#[cfg(not(feature = "network"))]
+pub async fn check_providers() -> Vec<(Provider, String)> {
+    Provider::ALL
+        .map(|provider| (provider, "not compiled in".into()))
+        .into()
+}
@@ -0,0 +1,349 @@
+use crate::{
+    danmaku::{Kind, Source},
+    http::{self, Provider},
+    live::{LiveMessage, Protocol},
+};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::Message;
+
+const CMD_HEARTBEAT: i64 = 5;
+const CMD_PUSH: i64 = 7;
+const CMD_REGISTER_GROUP: i64 = 16;
+const CMD_PUSH_V2: i64 = 22;
+
+const URI_MESSAGE_NOTICE: i64 = 1400;
+const URI_VIP_ENTER: i64 = 6110;
+const URI_GIFT: i64 = 6501;
+
+#[derive(Deserialize)]
+struct ProfileRoom {
+    data: ProfileRoomData,
+}
+
+#[derive(Deserialize)]
+struct ProfileRoomData {
+    #[serde(rename = "profileInfo")]
+    profile_info: ProfileInfo,
+}
+
+#[derive(Deserialize)]
+struct ProfileInfo {
+    uid: u64,
+}
+
+pub struct Huya {
+    room: String,
+    uid: u64,
+}
+
+impl Huya {
+    // 支持 https://www.huya.com/11342412 及自定义房间名
+    pub fn detect(url: &str) -> Option<Self> {
+        let (_, rest) = url.split_once("huya.com/")?;
+        let path = rest.split(['?', '#']).next().unwrap_or_default();
+        let room = path.split('/').find(|s| !s.is_empty())?;
+        Some(Self {
+            room: room.to_string(),
+            uid: 0,
+        })
+    }
+}
+
+// 虎牙使用的 TARS 编码
+// 浮点数与 map 只需跳过，解码为 Other
+enum Tars {
+    Int(i64),
+    Bytes(Vec<u8>),
+    List(Vec<Tars>),
+    Struct(Vec<(u8, Tars)>),
+    Other,
+}
+
+impl Tars {
+    fn field(&self, tag: u8) -> Option<&Tars> {
+        match self {
+            Tars::Struct(fields) => fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn int(&self, tag: u8) -> Option<i64> {
+        match self.field(tag)? {
+            Tars::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn string(&self, tag: u8) -> Option<String> {
+        match self.field(tag)? {
+            Tars::Bytes(value) => Some(String::from_utf8_lossy(value).into_owned()),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self, tag: u8) -> Option<&[u8]> {
+        match self.field(tag)? {
+            Tars::Bytes(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8]> {
+        if self.data.len() < n {
+            return Err(anyhow!("huya: truncated tars data"));
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn head(&mut self) -> Result<(u8, u8)> {
+        let b = self.take(1)?[0];
+        let tag = b >> 4;
+        let tag = if tag == 15 { self.take(1)?[0] } else { tag };
+        Ok((tag, b & 0xF))
+    }
+
+    fn length(&mut self) -> Result<usize> {
+        let (_, ty) = self.head()?;
+        match self.value(ty)? {
+            Tars::Int(n) if n >= 0 => Ok(n as usize),
+            _ => Err(anyhow!("huya: invalid tars length")),
+        }
+    }
+
+    fn value(&mut self, ty: u8) -> Result<Tars> {
+        Ok(match ty {
+            0 => Tars::Int(self.take(1)?[0] as i8 as i64),
+            1 => Tars::Int(i16::from_be_bytes(self.take(2)?.try_into()?) as i64),
+            2 => Tars::Int(i32::from_be_bytes(self.take(4)?.try_into()?) as i64),
+            3 => Tars::Int(i64::from_be_bytes(self.take(8)?.try_into()?)),
+            4 => {
+                self.take(4)?;
+                Tars::Other
+            }
+            5 => {
+                self.take(8)?;
+                Tars::Other
+            }
+            6 => {
+                let n = self.take(1)?[0] as usize;
+                Tars::Bytes(self.take(n)?.to_vec())
+            }
+            7 => {
+                let n = u32::from_be_bytes(self.take(4)?.try_into()?) as usize;
+                Tars::Bytes(self.take(n)?.to_vec())
+            }
+            8 => {
+                for _ in 0..self.length()? * 2 {
+                    let (_, ty) = self.head()?;
+                    self.value(ty)?;
+                }
+                Tars::Other
+            }
+            9 => {
+                let n = self.length()?;
+                let mut list = Vec::with_capacity(n.min(1024));
+                for _ in 0..n {
+                    let (_, ty) = self.head()?;
+                    list.push(self.value(ty)?);
+                }
+                Tars::List(list)
+            }
+            10 => self.fields()?,
+            12 => Tars::Int(0),
+            13 => {
+                self.head()?;
+                let n = self.length()?;
+                Tars::Bytes(self.take(n)?.to_vec())
+            }
+            _ => return Err(anyhow!("huya: unknown tars type {}", ty)),
+        })
+    }
+
+    fn fields(&mut self) -> Result<Tars> {
+        let mut fields = Vec::new();
+        while !self.data.is_empty() {
+            let (tag, ty) = self.head()?;
+            if ty == 11 {
+                break;
+            }
+            fields.push((tag, self.value(ty)?));
+        }
+        Ok(Tars::Struct(fields))
+    }
+}
+
+fn decode_struct(data: &[u8]) -> Result<Tars> {
+    Reader { data }.fields()
+}
+
+fn write_head(buf: &mut Vec<u8>, tag: u8, ty: u8) {
+    if tag < 15 {
+        buf.push(tag << 4 | ty);
+    } else {
+        buf.extend([0xF0 | ty, tag]);
+    }
+}
+
+fn write_int(buf: &mut Vec<u8>, tag: u8, value: i64) {
+    if value == 0 {
+        write_head(buf, tag, 12);
+    } else if let Ok(value) = i8::try_from(value) {
+        write_head(buf, tag, 0);
+        buf.push(value as u8);
+    } else if let Ok(value) = i16::try_from(value) {
+        write_head(buf, tag, 1);
+        buf.extend(value.to_be_bytes());
+    } else if let Ok(value) = i32::try_from(value) {
+        write_head(buf, tag, 2);
+        buf.extend(value.to_be_bytes());
+    } else {
+        write_head(buf, tag, 3);
+        buf.extend(value.to_be_bytes());
+    }
+}
+
+fn write_string(buf: &mut Vec<u8>, tag: u8, value: &str) {
+    if let Ok(n) = u8::try_from(value.len()) {
+        write_head(buf, tag, 6);
+        buf.push(n);
+    } else {
+        write_head(buf, tag, 7);
+        buf.extend((value.len() as u32).to_be_bytes());
+    }
+    buf.extend(value.as_bytes());
+}
+
+fn write_bytes(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    write_head(buf, tag, 13);
+    write_head(buf, 0, 0);
+    write_int(buf, 0, value.len() as i64);
+    buf.extend(value);
+}
+
+// WebSocketCommand { 0: iCmdType, 1: vData }
+fn command(cmd: i64, data: &[u8]) -> Message {
+    let mut buf = Vec::new();
+    write_int(&mut buf, 0, cmd);
+    write_bytes(&mut buf, 1, data);
+    Message::binary(buf)
+}
+
+impl Protocol for Huya {
+    const SOURCE: Source = Source::Huya;
+    const PROVIDER: Provider = Provider::Huya;
+    const HEARTBEAT: Duration = Duration::from_secs(60);
+
+    async fn prepare(&mut self) -> Result<String> {
+        if self.uid == 0 {
+            let profile: ProfileRoom = http::send(
+                Provider::Huya,
+                &format!(
+                    "https://mp.huya.com/cache.php?m=Live&do=profileRoom&roomid={}",
+                    self.room
+                ),
+            )
+            .await?
+            .json()
+            .await?;
+            self.uid = profile.data.profile_info.uid;
+        }
+        Ok("wss://cdnws.api.huya.com/".into())
+    }
+
+    fn handshake(&self) -> Vec<Message> {
+        // WSRegisterGroupReq { 0: vGroupId, 1: sToken }
+        let mut req = Vec::new();
+        write_head(&mut req, 0, 9);
+        write_int(&mut req, 0, 2);
+        write_string(&mut req, 0, &format!("live:{}", self.uid));
+        write_string(&mut req, 0, &format!("chat:{}", self.uid));
+        write_string(&mut req, 1, "");
+        vec![command(CMD_REGISTER_GROUP, &req)]
+    }
+
+    fn heartbeat(&self) -> Message {
+        command(CMD_HEARTBEAT, &[])
+    }
+
+    fn decode(&mut self, message: Message) -> Result<Vec<LiveMessage>> {
+        let command = decode_struct(&message.into_data())?;
+        // (iUri, sMsg)
+        let items = match command.int(0) {
+            Some(CMD_PUSH) => {
+                // WSPushMessage { 1: iUri, 2: sMsg }
+                let push = decode_struct(command.bytes(1).unwrap_or_default())?;
+                vec![(push.int(1), push.bytes(2).unwrap_or_default().to_vec())]
+            }
+            Some(CMD_PUSH_V2) => {
+                // WSPushMessage_V2 { 1: vMsgItem }，WSMsgItem { 0: iUri, 1: sMsg }
+                let push = decode_struct(command.bytes(1).unwrap_or_default())?;
+                match push.field(1) {
+                    Some(Tars::List(items)) => items
+                        .iter()
+                        .map(|item| (item.int(0), item.bytes(1).unwrap_or_default().to_vec()))
+                        .collect(),
+                    _ => Vec::new(),
+                }
+            }
+            _ => Vec::new(),
+        };
+
+        let mut messages = Vec::new();
+        for (uri, data) in items {
+            let message = match uri {
+                // MessageNotice { 0: tUserInfo, 3: sContent, 6: tBulletFormat { 0: iFontColor } }
+                Some(URI_MESSAGE_NOTICE) => {
+                    let notice = decode_struct(&data)?;
+                    let color = notice
+                        .field(6)
+                        .and_then(|f| f.int(0))
+                        .filter(|&c| c >= 0)
+                        .map(|c| c as u32 & 0xFFFFFF)
+                        .unwrap_or(0xFFFFFF);
+                    LiveMessage {
+                        message: notice.string(3).unwrap_or_default(),
+                        color,
+                        kind: Kind::Chat,
+                    }
+                }
+                // SendItemSubBroadcastPacket { 2: iItemCount, 6: sSenderNick }
+                Some(URI_GIFT) => {
+                    let gift = decode_struct(&data)?;
+                    LiveMessage {
+                        message: format!(
+                            "{} 送出礼物 ×{}",
+                            gift.string(6).unwrap_or_default(),
+                            gift.int(2).unwrap_or(1)
+                        ),
+                        color: 0xFFFFFF,
+                        kind: Kind::Gift,
+                    }
+                }
+                // VipEnterBanner { 1: sNickName }
+                Some(URI_VIP_ENTER) => {
+                    let enter = decode_struct(&data)?;
+                    LiveMessage {
+                        message: format!("{} 进入了直播间", enter.string(1).unwrap_or_default()),
+                        color: 0xFFFFFF,
+                        kind: Kind::Enter,
+                    }
+                }
+                _ => continue,
+            };
+            if !message.message.is_empty() {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+}
@@ -0,0 +1,1206 @@
+use crate::{
+    danmaku::{text, Danmaku, Kind, Mode, Source, Status, StatusInner},
+    options::{Easing, Options},
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{fmt::Write, ops::Range};
+
+const MAX_DURATION: f64 = 12.;
+pub const INTERVAL: f64 = 0.005;
+const MIN_STEP: f64 = INTERVAL / MAX_DURATION;
+const MAX_STEP: f64 = MIN_STEP * 1.3;
+// ease-out 时弹幕在画面左侧 EASE_ZONE 的范围内逐渐减速到 EASE_MIN 倍
+const EASE_ZONE: f64 = 0.3;
+const EASE_MIN: f64 = 0.6;
+// 两侧黑边至少能容纳的字数，否则仍在整个画面中滚动
+const SIDE_PANEL_CHARS: f64 = 8.;
+// two_columns 生效的最小宽高比
+const TWO_COLUMN_RATIO: f64 = 3.;
+
+#[derive(Default, Clone, Copy)]
+pub struct Params {
+    pub delay: f64,
+    pub stretch: f64,
+    pub speed: f64,
+    pub osd_width: f64,
+    pub osd_height: f64,
+    // osd-dimensions 中视频四周的黑边宽度，以 OSD 像素为单位
+    pub margin_left: f64,
+    pub margin_right: f64,
+    pub margin_top: f64,
+    pub margin_bottom: f64,
+    pub display_fps: f64,
+    // 暂停时只绘制当前位置，不推进滚动
+    pub pause: bool,
+    // 暂停时逐帧前进的播放时间，播放时为距上一次定时渲染经过的播放时间，为 0 时推进一帧
+    pub step: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Row {
+    end: f64,
+    step: f64,
+}
+
+// 弹幕在弹幕层中的位置，index 为其在弹幕列表中的下标
+pub struct Placement {
+    pub index: usize,
+    pub x: f64,
+    pub y: f64,
+    // 所在滚动区域在 Frame::regions 中的下标
+    pub region: usize,
+}
+
+pub struct Frame {
+    pub width: f64,
+    pub height: f64,
+    // safe_area 内缩后弹幕区域的边界
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+    // 各滚动区域的左边缘，宽度均为 region_width
+    pub regions: Vec<f64>,
+    pub region_width: f64,
+    pub font_size: f64,
+    pub spacing: f64,
+    // 当前显示在固定行中的醒目留言
+    pub paid: Option<usize>,
+    pub scrolling: Vec<Placement>,
+    // 顶部与底部的固定弹幕，x 为其水平中心
+    pub fixed: Vec<Placement>,
+}
+
+impl Frame {
+    // 距离 (x, y) 一个字以内的最近的弹幕
+    pub fn nearest(&self, comments: &[Danmaku], x: f64, y: f64) -> Option<usize> {
+        let h = self.font_size + self.spacing;
+        // 弹幕列表可能已在渲染之后被替换
+        let paid = self.paid.and_then(|index| {
+            let w = comments.get(index)?.count as f64 * self.font_size + self.spacing * 2.;
+            Some((index, self.paid_x() - w / 2., self.top, w))
+        });
+        let scrolling = self.scrolling.iter().filter_map(|p| {
+            let w = comments.get(p.index)?.count as f64 * self.font_size;
+            Some((p.index, p.x, p.y, w))
+        });
+        let fixed = self.fixed.iter().filter_map(|p| {
+            let w = comments.get(p.index)?.count as f64 * self.font_size;
+            Some((p.index, p.x - w / 2., p.y, w))
+        });
+        paid.into_iter()
+            .chain(scrolling)
+            .chain(fixed)
+            .map(|(index, left, top, w)| {
+                let dx = (left - x).max(x - left - w).max(0.);
+                let dy = (top - y).max(y - top - h).max(0.);
+                (index, dx.hypot(dy))
+            })
+            .filter(|&(_, d)| d <= self.font_size)
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(index, _)| index)
+    }
+
+    // 醒目留言居中显示在第一个滚动区域的顶部
+    pub fn paid_x(&self) -> f64 {
+        self.regions[0] + self.region_width / 2.
+    }
+}
+
+// 单条弹幕的运动轨迹，每一项为 (播放位置, x, y)
+pub type Trajectory = Vec<(f64, f64, f64)>;
+
+// 弹幕层的分辨率，保持与 OSD 相同的宽高比
+pub fn canvas(params: Params) -> (f64, f64) {
+    let mut width = 1920.;
+    let mut height = 1080.;
+    let ratio = params.osd_width / params.osd_height;
+    if width / height < ratio {
+        height = width / ratio;
+    } else if width / height > ratio {
+        width = height * ratio;
+    }
+    (width, height)
+}
+
+// 一个 OSD 像素在画布中的大小
+fn osd_pixel(params: Params, width: f64) -> f64 {
+    if params.osd_width > 0. {
+        width / params.osd_width
+    } else {
+        1.
+    }
+}
+
+// 扣除底部保留空间后的高度，reserved_pixels 为 OSD 像素，与 reserved_space 取较大者
+fn usable_height(height: f64, px: f64, options: &Options) -> f64 {
+    let pixels = options.reserved_pixels.map_or(0., |pixels| pixels * px);
+    (height - (height * options.reserved_space).max(pixels)).max(0.)
+}
+
+// 开启 pixel_snap 时按显示器刷新率渲染
+pub fn frame_interval(params: Params, options: Options) -> f64 {
+    if options.pixel_snap && params.display_fps > 0. {
+        1. / params.display_fps
+    } else {
+        INTERVAL
+    }
+}
+
+pub fn reset_status(comments: &mut [Danmaku]) {
+    for comment in comments {
+        comment.status = Status::Uninitialized;
+    }
+}
+
+// 计算播放位置 pos 处各弹幕的位置，并将滚动弹幕推进一帧
+pub fn layout(
+    comments: &mut [Danmaku],
+    params: Params,
+    options: Options,
+    pos: f64,
+    rng: &mut impl Rng,
+) -> Frame {
+    layout_in(comments, &text(), params, options, pos, rng)
+}
+
+// 弹幕文本存放在 text 而不是全局的 TEXT 中，供 C 接口使用
+pub fn layout_in(
+    comments: &mut [Danmaku],
+    text: &str,
+    params: Params,
+    options: Options,
+    pos: f64,
+    rng: &mut impl Rng,
+) -> Frame {
+    let (full_width, full_height) = canvas(params);
+    let spacing = options.font_size / 10.;
+    // 输出坐标对齐到屏幕像素，避免小数坐标造成的抖动
+    let px = osd_pixel(params, full_width);
+    // 不含黑边的视频区域
+    let video_width = (full_width - (params.margin_left + params.margin_right) * px).max(0.);
+    let video_height = (full_height - (params.margin_top + params.margin_bottom) * px).max(0.);
+    let (mut left, top) = (
+        params.margin_left * px + video_width * options.safe_area,
+        params.margin_top * px + video_height * options.safe_area,
+    );
+    // 以下均为内缩后的弹幕区域
+    let (mut width, height) = (
+        video_width * (1. - options.safe_area * 2.),
+        video_height * (1. - options.safe_area * 2.),
+    );
+    // 竖屏视频两侧的黑边足够宽时，弹幕只在两侧滚动，不遮挡视频
+    let inset = full_width * options.safe_area;
+    let panel = params.margin_left.min(params.margin_right) * px - inset;
+    let regions = if options.side_panels && panel >= options.font_size * SIDE_PANEL_CHARS {
+        (left, width) = (inset, panel);
+        vec![inset, full_width - inset - panel]
+    } else if options.two_columns && width / height >= TWO_COLUMN_RATIO {
+        // 超宽屏分为左右两个独立的滚动区域
+        width /= 2.;
+        vec![left, left + width]
+    } else {
+        vec![left]
+    };
+    let snap = |v: f64| (v / px).round() * px;
+    // 同一行中相邻弹幕的最小间距
+    let gap = options.lane_gap.map_or(spacing, |gap| gap * px);
+    // 每次渲染前进的播放时间，以 INTERVAL 为单位
+    let advance = if params.pause || params.step > 0. {
+        params.step / INTERVAL
+    } else {
+        frame_interval(params, options) / INTERVAL * params.speed
+    };
+    // 按前一条弹幕全程以最低速度移动估计后一条弹幕是否会追上
+    let min_ease = match options.easing {
+        Easing::Linear => 1.,
+        Easing::EaseOut => EASE_MIN,
+    };
+    // 醒目留言固定占用第一行
+    let paid_rows = usize::from(options.paid_lane);
+    // 每个区域的行数，行按区域依次编号
+    let region_rows = ((usable_height(height, px, &options) / (options.font_size + spacing))
+        as usize)
+        .saturating_sub(paid_rows)
+        .max(1);
+    let mut rows = vec![
+        Row {
+            end: 0.,
+            step: MIN_STEP,
+        };
+        region_rows * regions.len()
+    ];
+
+    let mut paid = None;
+    if options.paid_lane {
+        // 同时出现的醒目留言依次排队显示
+        let mut end = f64::MIN;
+        for (index, comment) in comments
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| !c.blocked && c.kind == Kind::Paid)
+        {
+            let start =
+                (comment.time * params.stretch + params.delay + options.display_delay).max(end);
+            if start > pos {
+                break;
+            }
+            end = start + options.paid_duration;
+            if pos < end {
+                paid = Some(index);
+                break;
+            }
+        }
+    }
+
+    // 顶部与底部各行中固定弹幕的消失时间，自上而下与自下而上编号，
+    // 与滚动弹幕一样按区域依次编号
+    let mut top_rows = vec![f64::MIN; region_rows * regions.len()];
+    let mut bottom_rows = vec![f64::MIN; region_rows * regions.len()];
+    let mut fixed = Vec::new();
+
+    let text = options.unique_priority.then_some(text);
+    let mut scrolling = Vec::new();
+    // 画面已满时跳过新出现的弹幕，先出现的弹幕优先，结果不随渲染次数变化
+    let full = |scrolling: &Vec<Placement>, fixed: &Vec<Placement>| {
+        options.max_onscreen > 0 && scrolling.len() + fixed.len() >= options.max_onscreen
+    };
+    'it: for (index, comment) in comments
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, c)| !(c.blocked || options.paid_lane && c.kind == Kind::Paid))
+    {
+        let time = comment.time * params.stretch + params.delay + options.display_delay;
+        if time > pos {
+            break;
+        }
+
+        if comment.mode != Mode::Scroll {
+            let end = time + options.static_duration;
+            if pos >= end {
+                continue;
+            }
+            let pool = if comment.mode == Mode::Top {
+                &mut top_rows
+            } else {
+                &mut bottom_rows
+            };
+            let row = match comment.status {
+                Status::Status(StatusInner { row, .. }) => row.min(pool.len() - 1),
+                Status::Overlapping => continue,
+                Status::Uninitialized | Status::Delayed if full(&scrolling, &fixed) => {
+                    comment.status = Status::Overlapping;
+                    continue;
+                }
+                Status::Uninitialized | Status::Delayed => {
+                    // 没有空闲的行时与最早消失的弹幕重叠
+                    let row = match pool.iter().position(|&end| end <= time) {
+                        Some(row) => row,
+                        None if options.no_overlap => {
+                            comment.status = Status::Overlapping;
+                            continue;
+                        }
+                        None => (0..pool.len())
+                            .min_by(|&a, &b| pool[a].partial_cmp(&pool[b]).unwrap())
+                            .unwrap(),
+                    };
+                    comment.status.insert(StatusInner {
+                        x: 0.,
+                        row,
+                        step: 0.,
+                    });
+                    row
+                }
+            };
+            pool[row] = pool[row].max(end);
+            // 居中显示在所在的区域中
+            let region = row / region_rows;
+            let line = match comment.mode {
+                Mode::Top => row % region_rows,
+                _ => region_rows - 1 - row % region_rows,
+            };
+            fixed.push(Placement {
+                index,
+                x: snap(regions[region] + width / 2.),
+                y: snap(top + (line + paid_rows) as f64 * (options.font_size + spacing)),
+                region,
+            });
+            continue;
+        }
+
+        let delayed = matches!(comment.status, Status::Delayed);
+        if matches!(comment.status, Status::Uninitialized | Status::Delayed)
+            && full(&scrolling, &fixed)
+        {
+            comment.status = Status::Overlapping;
+            continue;
+        }
+        let status = match &mut comment.status {
+            Status::Status(status) => status,
+            Status::Overlapping => continue,
+            Status::Uninitialized | Status::Delayed => 'status: {
+                let ticks = if delayed { 0. } else { (pos - time) / INTERVAL };
+                // 完整经过画面的时间限制在 min_display_time 与 max_display_time 之间，冲突时前者优先
+                let distance = width + comment.count as f64 * options.font_size + spacing;
+                let step_for = |t: f64| distance * INTERVAL / (width * options.speed * t);
+                let fastest = options.min_display_time.map_or(f64::INFINITY, step_for);
+                let slowest = options.max_display_time.map_or(0., step_for);
+                let min_step = MIN_STEP.max(slowest).min(fastest);
+                let max_step = MAX_STEP.min(fastest).max(min_step);
+                let free = |row: &Row| row.end < width - width * ticks * MIN_STEP;
+                let lanes = lanes(options, comment.source, rows.len());
+                // 空闲的行不足四分之一时留给信息量更大的弹幕
+                if options.no_overlap
+                    && rows[lanes.clone()].iter().filter(|row| free(row)).count() * 4 <= lanes.len()
+                    && text.is_some_and(|text| low_information(comment.message.get(text)))
+                {
+                    comment.status = Status::Overlapping;
+                    continue 'it;
+                }
+                for (row, status) in rows.iter().enumerate().skip(lanes.start).take(lanes.len()) {
+                    if free(status) {
+                        let max_step = if status.end == 0. {
+                            max_step
+                        } else {
+                            // 1 / max_step - ticks = status.end / width / status.step
+                            let row_step =
+                                1. / (ticks + status.end / width / (status.step * min_ease));
+                            row_step.min(max_step)
+                        };
+                        // 最慢也会追上前一条弹幕
+                        if max_step < min_step {
+                            continue;
+                        }
+                        let step = if max_step > min_step {
+                            rng.gen_range(min_step..max_step)
+                        } else {
+                            min_step
+                        };
+                        let x = width - width * ticks * step;
+                        break 'status comment.status.insert(StatusInner { x, row, step });
+                    }
+                }
+                if options.no_overlap {
+                    // 弹幕过于密集时推迟显示，超过 max_lateness 仍无空闲的行则不再显示
+                    comment.status = if pos - time < options.max_lateness {
+                        Status::Delayed
+                    } else {
+                        Status::Overlapping
+                    };
+                    continue 'it;
+                }
+                let row = rows
+                    .iter()
+                    .enumerate()
+                    .skip(lanes.start)
+                    .take(lanes.len())
+                    .min_by(|a, b| a.1.end.partial_cmp(&b.1.end).unwrap())
+                    .map(|(row, _)| row)
+                    .unwrap();
+                let step = min_step;
+                let x = width - width * ticks * step;
+                comment.status.insert(StatusInner { x, row, step })
+            }
+        };
+        if status.x + comment.count as f64 * options.font_size + spacing <= 0. {
+            continue;
+        }
+        // 窗口大小改变后已有弹幕的行可能超出范围
+        let region = (status.row / region_rows).min(regions.len() - 1);
+        let row = status.row % region_rows;
+        scrolling.push(Placement {
+            index,
+            x: snap(regions[region] + status.x),
+            y: snap(top + (row + paid_rows) as f64 * (options.font_size + spacing)),
+            region,
+        });
+
+        let ease = match options.easing {
+            Easing::Linear => 1.,
+            Easing::EaseOut => {
+                EASE_MIN + (1. - EASE_MIN) * (status.x / (width * EASE_ZONE)).clamp(0., 1.)
+            }
+        };
+        let dx = width * status.step * options.speed * advance * ease;
+        // 每帧移动整数个像素
+        status.x -= if options.pixel_snap && dx > 0. {
+            (dx / px).round().max(1.) * px
+        } else {
+            dx
+        };
+        if let Some(row) = rows.get_mut(status.row) {
+            let end = status.x + comment.count as f64 * options.font_size + gap;
+            if end / status.step > row.end / row.step {
+                *row = Row {
+                    end,
+                    step: status.step,
+                };
+            }
+        }
+    }
+
+    Frame {
+        width: full_width,
+        height: full_height,
+        left,
+        top,
+        right: regions[regions.len() - 1] + width,
+        bottom: top + height,
+        regions,
+        region_width: width,
+        font_size: options.font_size,
+        spacing,
+        paid,
+        scrolling,
+        fixed,
+    }
+}
+
+// 弹幕来源可使用的行，source_lanes 中的来源各自占用相同数量的连续行
+fn lanes(options: Options, source: Source, rows: usize) -> Range<usize> {
+    let bands = options.source_lanes.iter().max().copied().unwrap_or(0) as usize;
+    match options.source_lanes[source as usize] as usize {
+        0 => 0..rows,
+        band if rows >= bands => rows * (band - 1) / bands..rows * band / bands,
+        // 行数少于来源数时共用所有行
+        _ => 0..rows,
+    }
+}
+
+// 生成弹幕层的 ASS 事件，每行一条
+pub fn events(comments: &[Danmaku], frame: &Frame, options: Options) -> String {
+    events_in(comments, &text(), frame, options)
+}
+
+pub fn events_in(comments: &[Danmaku], text: &str, frame: &Frame, options: Options) -> String {
+    // 每条弹幕共用的样式，避免在循环中重复格式化
+    let style = format!(
+        "\\alpha&H{:x}\\fs{}\\bord{}\\shad0\\b1\\q2}}",
+        options.transparency, options.font_size, options.border
+    );
+    let mut ryu = ryu::Buffer::new();
+    let (top, spacing) = (frame.top, frame.spacing);
+    // 有多个滚动区域时裁剪到各自的区域内
+    let clip = (frame.regions.len() > 1).then(|| {
+        frame
+            .regions
+            .iter()
+            .map(|left| {
+                format!(
+                    "\\clip({},{},{},{})",
+                    left,
+                    top,
+                    left + frame.region_width,
+                    frame.bottom
+                )
+            })
+            .collect::<Vec<_>>()
+    });
+    let mut buf = String::new();
+    if let Some(comment) = frame.paid.map(|index| &comments[index]) {
+        let w = comment.count as f64 * options.font_size + spacing * 2.;
+        let h = options.font_size + spacing;
+        _ = writeln!(
+            buf,
+            "{{\\an8\\pos({},{})\\c&H{:06x}&\\alpha&H{:x}\\bord0\\shad0\\p1}}m 0 0 l {w} 0 {w} {h} 0 {h}",
+            frame.paid_x(),
+            top,
+            options.paid_color,
+            options.transparency,
+        );
+        _ = write!(
+            buf,
+            "{{\\an8\\pos({},{})\\c&H",
+            frame.paid_x(),
+            top + spacing / 2.
+        );
+        push_color(&mut buf, [comment.b, comment.g, comment.r]);
+        buf.push('&');
+        buf.push_str(&style);
+        buf.push_str(comment.message.get(text));
+    }
+
+    for placement in &frame.scrolling {
+        let comment = &comments[placement.index];
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str("{\\pos(");
+        buf.push_str(ryu.format(placement.x));
+        buf.push(',');
+        buf.push_str(ryu.format(placement.y));
+        buf.push_str(")\\c&H");
+        push_color(&mut buf, [comment.b, comment.g, comment.r]);
+        buf.push('&');
+        if options.highlight_new && comment.new {
+            buf.push_str("\\3c&H00D7FF&");
+        }
+        if let Some(clip) = &clip {
+            buf.push_str(&clip[placement.region]);
+        }
+        buf.push_str(&style);
+        buf.push_str(comment.message.get(text));
+    }
+
+    for placement in &frame.fixed {
+        let comment = &comments[placement.index];
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str("{\\an8\\pos(");
+        buf.push_str(ryu.format(placement.x));
+        buf.push(',');
+        buf.push_str(ryu.format(placement.y));
+        buf.push_str(")\\c&H");
+        push_color(&mut buf, [comment.b, comment.g, comment.r]);
+        buf.push('&');
+        if options.highlight_new && comment.new {
+            buf.push_str("\\3c&H00D7FF&");
+        }
+        if let Some(clip) = &clip {
+            buf.push_str(&clip[placement.region]);
+        }
+        buf.push_str(&style);
+        buf.push_str(comment.message.get(text));
+    }
+    buf
+}
+
+// ASS 时间格式 h:mm:ss.cc
+fn ass_time(t: f64) -> String {
+    let cs = (t.max(0.) * 100.).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        cs / 360000,
+        cs / 6000 % 60,
+        cs / 100 % 60,
+        cs % 100
+    )
+}
+
+// 弹幕文字中的 { } 会被当作覆盖标签，\n、\N、\h 会被当作换行与空格，
+// 反斜杠后插入零宽空格使其按原样显示
+fn escape_ass(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\u{200B}"),
+            '{' => escaped.push_str("\\{"),
+            '}' => escaped.push_str("\\}"),
+            '\n' | '\r' => escaped.push(' '),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// 将所有弹幕写为独立的 ASS 字幕，滚动弹幕以 \move 匀速经过画面，可用于 ffmpeg 压制
+// 与实时渲染不同，每条弹幕在出现时一次性分配行，滚动弹幕全部以 MAX_DURATION 对应的速度移动
+pub fn export_ass(comments: &[Danmaku], params: Params, options: Options) -> String {
+    let text = text();
+    let (width, height) = canvas(params);
+    let spacing = options.font_size / 10.;
+    let line = options.font_size + spacing;
+    let speed = width * options.speed / MAX_DURATION;
+    let px = osd_pixel(params, width);
+    let rows = ((usable_height(height, px, &options) / line) as usize).max(1);
+    // 各行可放入下一条弹幕的时间
+    let mut scroll_rows = vec![f64::MIN; rows];
+    let mut top_rows = vec![f64::MIN; rows];
+    let mut bottom_rows = vec![f64::MIN; rows];
+    let mut buf = String::new();
+    _ = write!(
+        buf,
+        "[Script Info]\nScriptType: v4.00+\nPlayResX: {width}\nPlayResY: {height}\nWrapStyle: 2\nScaledBorderAndShadow: yes\n\n\
+         [V4+ Styles]\nFormat: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding\n\
+         Style: Danmaku,sans-serif,{},&H{:02X}FFFFFF,&H{:02X}FFFFFF,&H{:02X}000000,&H{:02X}000000,-1,0,0,0,100,100,0,0,1,{},0,7,0,0,0,1\n\n\
+         [Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n",
+        options.font_size,
+        options.transparency,
+        options.transparency,
+        options.transparency,
+        options.transparency,
+        options.border,
+    );
+    for comment in comments.iter().filter(|c| !c.blocked) {
+        // display_delay 只推迟实时显示，不属于弹幕的时间轴
+        let time = comment.time * params.stretch + params.delay;
+        let len = comment.count as f64 * options.font_size;
+        let (pool, end) = match comment.mode {
+            Mode::Scroll => (&mut scroll_rows, time + (width + len) / speed),
+            Mode::Top => (&mut top_rows, time + options.static_duration),
+            Mode::Bottom => (&mut bottom_rows, time + options.static_duration),
+        };
+        let row = match pool.iter().position(|&free| free <= time) {
+            Some(row) => row,
+            None if options.no_overlap => continue,
+            None => (0..rows)
+                .min_by(|&a, &b| pool[a].partial_cmp(&pool[b]).unwrap())
+                .unwrap(),
+        };
+        // 滚动弹幕的尾部完全进入画面后，同一行才能放入下一条
+        pool[row] = match comment.mode {
+            Mode::Scroll => time + (len + spacing) / speed,
+            _ => end,
+        };
+        _ = write!(
+            buf,
+            "Dialogue: 0,{},{},Danmaku,,0,0,0,,{{",
+            ass_time(time),
+            ass_time(end)
+        );
+        match comment.mode {
+            Mode::Scroll => {
+                let y = row as f64 * line;
+                _ = write!(buf, "\\move({},{},{},{})", width, y, -len, y);
+            }
+            Mode::Top => _ = write!(buf, "\\an8\\pos({},{})", width / 2., row as f64 * line),
+            Mode::Bottom => {
+                _ = write!(
+                    buf,
+                    "\\an8\\pos({},{})",
+                    width / 2.,
+                    (rows - 1 - row) as f64 * line
+                )
+            }
+        }
+        buf.push_str("\\c&H");
+        push_color(&mut buf, [comment.b, comment.g, comment.r]);
+        buf.push_str("&}");
+        buf.push_str(&escape_ass(comment.message.get(&text)));
+        buf.push('\n');
+    }
+    buf
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+// ASS 颜色顺序为 BBGGRR
+fn push_color(buf: &mut String, bgr: [u8; 3]) {
+    for c in bgr {
+        buf.push(HEX[usize::from(c >> 4)] as char);
+        buf.push(HEX[usize::from(c & 0xF)] as char);
+    }
+}
+
+// 由不超过两种字符组成的弹幕，如 666、哈哈哈哈、23333
+fn low_information(message: &str) -> bool {
+    let mut chars = message.chars().collect::<Vec<_>>();
+    chars.sort_unstable();
+    chars.dedup();
+    chars.len() <= 2
+}
+
+// 不依赖 mpv，模拟从头播放 duration 秒，返回每条弹幕的运动轨迹
+pub fn simulate(comments: &mut [Danmaku], options: Options, duration: f64) -> Vec<Trajectory> {
+    let ticks = (duration / INTERVAL) as usize;
+    trace(comments, options, (0..=ticks).map(|i| i as f64 * INTERVAL))
+}
+
+// 依次在给定的播放位置渲染，位置不连续时视为跳转
+fn trace(
+    comments: &mut [Danmaku],
+    options: Options,
+    positions: impl IntoIterator<Item = f64>,
+) -> Vec<Trajectory> {
+    let params = Params {
+        stretch: 1.,
+        speed: 1.,
+        osd_width: 1920.,
+        osd_height: 1080.,
+        ..Default::default()
+    };
+    // 固定种子使结果可复现
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut trajectories = vec![Trajectory::new(); comments.len()];
+    let mut last = None;
+    for pos in positions {
+        if last.is_some_and(|last: f64| (pos - last - INTERVAL).abs() > INTERVAL / 2.) {
+            reset_status(comments);
+        }
+        last = Some(pos);
+        let frame = layout(comments, params, options, pos, &mut rng);
+        if let Some(index) = frame.paid {
+            trajectories[index].push((pos, frame.paid_x(), frame.top));
+        }
+        for placement in frame.scrolling.into_iter().chain(frame.fixed) {
+            trajectories[placement.index].push((pos, placement.x, placement.y));
+        }
+    }
+    trajectories
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::danmaku::Text;
+    use std::{env, fmt::Write, fs, path::PathBuf};
+
+    fn comment(time: f64, message: &str, kind: Kind) -> Danmaku {
+        from(time, message, kind, Source::Unknown)
+    }
+
+    fn from(time: f64, message: &str, kind: Kind, source: Source) -> Danmaku {
+        Danmaku {
+            message: Text::new(message),
+            sender: Text::new(""),
+            count: message.chars().count(),
+            time,
+            r: 0xFF,
+            g: 0xFF,
+            b: 0xFF,
+            source,
+            kind,
+            mode: Mode::Scroll,
+            blocked: false,
+            status: Status::Uninitialized,
+            new: false,
+        }
+    }
+
+    // 每段连续的轨迹只记录起点与终点
+    fn summary(trajectories: &[Trajectory]) -> String {
+        let mut s = String::new();
+        for (index, trajectory) in trajectories.iter().enumerate() {
+            if trajectory.is_empty() {
+                _ = writeln!(s, "{index}: hidden");
+                continue;
+            }
+            _ = writeln!(s, "{index}:");
+            let mut start = trajectory[0];
+            for (i, &point) in trajectory.iter().enumerate() {
+                let next = trajectory.get(i + 1);
+                if !next.is_some_and(|next| (next.0 - point.0 - INTERVAL).abs() <= INTERVAL / 2.) {
+                    _ = writeln!(
+                        s,
+                        "  {:.3} ({:.1}, {:.1}) -> {:.3} ({:.1}, {:.1})",
+                        start.0, start.1, start.2, point.0, point.1, point.2
+                    );
+                    if let Some(&next) = next {
+                        start = next;
+                    }
+                }
+            }
+        }
+        s
+    }
+
+    // 设置 UPDATE_GOLDEN=1 时重新生成结果文件
+    fn golden(name: &str, actual: &str) {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/golden")
+            .join(format!("{name}.txt"));
+        if env::var_os("UPDATE_GOLDEN").is_some() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, actual).unwrap();
+            return;
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        assert_eq!(expected, actual, "layout differs from {}", path.display());
+    }
+
+    #[test]
+    fn dense_burst() {
+        let mut comments = (0..60)
+            .map(|i| comment(1. + i as f64 * 0.01, "密集弹幕测试", Kind::Chat))
+            .collect::<Vec<_>>();
+        let trajectories = simulate(&mut comments, Options::default(), 15.);
+        golden("dense_burst", &summary(&trajectories));
+    }
+
+    #[test]
+    fn mixed_lengths() {
+        let mut comments = (0..30)
+            .map(|i| comment(i as f64 * 0.2, &"长".repeat(1 + i * 7 % 40), Kind::Chat))
+            .collect::<Vec<_>>();
+        let trajectories = simulate(&mut comments, Options::default(), 20.);
+        golden("mixed_lengths", &summary(&trajectories));
+    }
+
+    #[test]
+    fn overlap() {
+        let mut comments = (0..40)
+            .map(|i| comment(2. + i as f64 * 0.05, "重叠", Kind::Chat))
+            .collect::<Vec<_>>();
+        let options = Options {
+            no_overlap: false,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 16.);
+        golden("overlap", &summary(&trajectories));
+    }
+
+    #[test]
+    fn unique_priority() {
+        let mut comments = (0..80)
+            .map(|i| {
+                let message = if i % 3 == 0 {
+                    "这条弹幕有内容"
+                } else {
+                    "哈哈哈哈"
+                };
+                comment(1. + i as f64 * 0.02, message, Kind::Chat)
+            })
+            .collect::<Vec<_>>();
+        let options = Options {
+            unique_priority: true,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 15.);
+        golden("unique_priority", &summary(&trajectories));
+    }
+
+    #[test]
+    fn max_lateness() {
+        let mut comments = (0..60)
+            .map(|i| comment(1. + i as f64 * 0.01, "密集弹幕测试", Kind::Chat))
+            .collect::<Vec<_>>();
+        let options = Options {
+            max_lateness: 5.,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 20.);
+        golden("max_lateness", &summary(&trajectories));
+    }
+
+    #[test]
+    fn source_lanes() {
+        let mut comments = (0..40)
+            .map(|i| {
+                let source = if i % 2 == 0 {
+                    Source::Bilibili
+                } else {
+                    Source::Gamer
+                };
+                from(1. + i as f64 * 0.1, "分区弹幕", Kind::Chat, source)
+            })
+            .collect::<Vec<_>>();
+        let mut options = Options::default();
+        options.source_lanes[Source::Bilibili as usize] = 1;
+        options.source_lanes[Source::Gamer as usize] = 2;
+        let trajectories = simulate(&mut comments, options, 16.);
+        golden("source_lanes", &summary(&trajectories));
+    }
+
+    #[test]
+    fn seeks() {
+        let mut comments = (0..40)
+            .map(|i| comment(i as f64 * 0.5, "跳转", Kind::Chat))
+            .collect::<Vec<_>>();
+        let ticks = |from: f64, to: f64| {
+            let n = ((to - from) / INTERVAL) as usize;
+            (0..=n).map(move |i| from + i as f64 * INTERVAL)
+        };
+        // 向后跳转、再向前跳转
+        let positions = ticks(0., 5.).chain(ticks(12., 16.)).chain(ticks(2., 4.));
+        let trajectories = trace(&mut comments, Options::default(), positions);
+        golden("seeks", &summary(&trajectories));
+    }
+
+    #[test]
+    fn paid_lane() {
+        let mut comments = vec![
+            comment(0.5, "普通弹幕", Kind::Chat),
+            comment(1., "醒目留言一", Kind::Paid),
+            comment(2., "醒目留言二", Kind::Paid),
+            comment(3., "普通弹幕", Kind::Chat),
+        ];
+        let trajectories = simulate(&mut comments, Options::default(), 25.);
+        golden("paid_lane", &summary(&trajectories));
+    }
+
+    #[test]
+    fn pause() {
+        let mut comments = vec![comment(0., "暂停", Kind::Chat)];
+        let mut params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 1920.,
+            osd_height: 1080.,
+            pause: true,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut x =
+            || layout(&mut comments, params, Options::default(), 2., &mut rng).scrolling[0].x;
+        // 暂停时重复渲染位置不变，继续播放后从同一位置开始移动
+        let paused = x();
+        assert_eq!(paused, x());
+        params.pause = false;
+        let mut x =
+            || layout(&mut comments, params, Options::default(), 2., &mut rng).scrolling[0].x;
+        assert_eq!(paused, x());
+        assert!(x() < paused);
+    }
+
+    #[test]
+    fn ease_out() {
+        let mut comments = (0..60)
+            .map(|i| comment(1. + i as f64 * 0.05, &"缓".repeat(1 + i % 12), Kind::Chat))
+            .collect::<Vec<_>>();
+        let options = Options {
+            easing: Easing::EaseOut,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 20.);
+        golden("ease_out", &summary(&trajectories));
+    }
+
+    #[test]
+    fn display_time() {
+        let mut comments = vec![
+            comment(0., "短", Kind::Chat),
+            comment(0., &"长".repeat(60), Kind::Chat),
+        ];
+        let options = Options {
+            min_display_time: Some(20.),
+            max_display_time: Some(6.),
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 30.);
+        let duration = |t: &Trajectory| t.last().unwrap().0 - t[0].0;
+        // 冲突时以 min_display_time 为准
+        assert!(duration(&trajectories[0]) >= 20. - INTERVAL * 2.);
+        assert!(duration(&trajectories[1]) >= 20. - INTERVAL * 2.);
+
+        let options = Options {
+            min_display_time: Some(1.),
+            max_display_time: Some(6.),
+            ..Default::default()
+        };
+        reset_status(&mut comments);
+        let trajectories = simulate(&mut comments, options, 30.);
+        assert!(duration(&trajectories[0]) <= 6. + INTERVAL * 2.);
+        assert!(duration(&trajectories[1]) <= 6. + INTERVAL * 2.);
+    }
+
+    #[test]
+    fn safe_area() {
+        let mut comments = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "安全区域", Kind::Chat))
+            .collect::<Vec<_>>();
+        let options = Options {
+            safe_area: 0.05,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 1.);
+        // 每条弹幕从内缩后的右边缘进入，所有行都在上下边距之间
+        for trajectory in trajectories.iter().filter(|t| !t.is_empty()) {
+            assert!(trajectory[0].1 <= 1920. * 0.95);
+            assert!(trajectory.iter().all(|&(_, _, y)| y >= 54.));
+            assert!(trajectory.iter().all(|&(_, _, y)| y + 44. <= 1080. * 0.95));
+        }
+    }
+
+    #[test]
+    fn reserved_pixels() {
+        let mut comments = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "保留", Kind::Chat))
+            .collect::<Vec<_>>();
+        // 上下各有 140 像素的黑边
+        let params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 1920.,
+            osd_height: 1080.,
+            margin_top: 140.,
+            margin_bottom: 140.,
+            ..Default::default()
+        };
+        let options = Options {
+            reserved_pixels: Some(200.),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let frame = layout(&mut comments, params, options, 1., &mut rng);
+        // 视频底边之上的 200 像素不放弹幕
+        assert!(!frame.scrolling.is_empty());
+        assert!(frame.scrolling.iter().all(|p| p.y + 44. <= 940. - 200.));
+        let ass = export_ass(&comments, params, options);
+        assert!(ass.lines().filter(|l| l.starts_with("Dialogue")).all(|l| {
+            let y = l
+                .split("\\move(")
+                .nth(1)
+                .unwrap()
+                .split(',')
+                .nth(1)
+                .unwrap();
+            y.parse::<f64>().unwrap() + 44. <= 1080. - 200.
+        }));
+    }
+
+    #[test]
+    fn side_panels() {
+        let mut comments = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "竖屏", Kind::Chat))
+            .collect::<Vec<_>>();
+        // 9:16 的视频两侧各有 656 像素的黑边
+        let params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 1920.,
+            osd_height: 1080.,
+            margin_left: 656.,
+            margin_right: 656.,
+            ..Default::default()
+        };
+        let options = Options {
+            side_panels: true,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let frame = layout(&mut comments, params, options, 1., &mut rng);
+        assert_eq!(frame.regions, [0., 1264.]);
+        assert!(frame.scrolling.iter().any(|p| p.region == 1));
+        for placement in &frame.scrolling {
+            let left = frame.regions[placement.region];
+            assert!(placement.x >= left - 80. && placement.x <= left + 656.);
+        }
+        assert!(events(&comments, &frame, options).contains("\\clip(1264,0,1920,1080)"));
+    }
+
+    #[test]
+    fn max_onscreen() {
+        let mut comments = (0..60)
+            .map(|i| comment(1. + i as f64 * 0.05, "上限", Kind::Chat))
+            .collect::<Vec<_>>();
+        let options = Options {
+            max_onscreen: 5,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 8.);
+        // 任意时刻最多显示 5 条，先出现的弹幕优先
+        for tick in (0..=1600).step_by(10) {
+            let pos = tick as f64 * INTERVAL;
+            let visible = trajectories
+                .iter()
+                .filter(|t| t.iter().any(|p| (p.0 - pos).abs() < INTERVAL / 2.))
+                .count();
+            assert!(visible <= 5);
+        }
+        assert!(trajectories[..5].iter().all(|t| !t.is_empty()));
+        assert!(trajectories.iter().any(|t| t.is_empty()));
+    }
+
+    #[test]
+    fn letterbox() {
+        let mut comments = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "宽银幕", Kind::Chat))
+            .collect::<Vec<_>>();
+        // 2.39:1 的视频上下各有 138 像素的黑边
+        let params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 1920.,
+            osd_height: 1080.,
+            margin_top: 138.,
+            margin_bottom: 138.,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let frame = layout(&mut comments, params, Options::default(), 1., &mut rng);
+        assert_eq!((frame.top, frame.bottom), (138., 942.));
+        for placement in &frame.scrolling {
+            assert!(placement.y >= 138. && placement.y + frame.font_size <= 942.);
+        }
+    }
+
+    #[test]
+    fn two_columns() {
+        let mut comments = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "超宽屏", Kind::Chat))
+            .collect::<Vec<_>>();
+        let params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 5120.,
+            osd_height: 1440.,
+            ..Default::default()
+        };
+        let options = Options {
+            two_columns: true,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let frame = layout(&mut comments, params, options, 1., &mut rng);
+        assert_eq!(frame.regions, [0., 960.]);
+        assert!(frame.scrolling.iter().any(|p| p.region == 0));
+        assert!(frame.scrolling.iter().any(|p| p.region == 1));
+        // 顶部弹幕占满左栏后居中显示在右栏
+        let mut fixed = (0..60)
+            .map(|i| comment(i as f64 * 0.01, "顶部", Kind::Chat))
+            .collect::<Vec<_>>();
+        for comment in &mut fixed {
+            comment.mode = Mode::Top;
+        }
+        let frame = layout(&mut fixed, params, options, 1., &mut rng);
+        assert!(frame.fixed.iter().any(|p| p.region == 0 && p.x == 480.));
+        assert!(frame.fixed.iter().any(|p| p.region == 1 && p.x == 1440.));
+        // 16:9 时不分栏
+        let frame = layout(
+            &mut comments,
+            Params {
+                osd_width: 1920.,
+                osd_height: 1080.,
+                ..params
+            },
+            options,
+            1.,
+            &mut rng,
+        );
+        assert_eq!(frame.regions, [0.]);
+    }
+
+    #[test]
+    fn static_modes() {
+        let mut comments = vec![
+            comment(1., "顶部一", Kind::Chat),
+            comment(1.5, "顶部二", Kind::Chat),
+            comment(2., "底部", Kind::Chat),
+            comment(3., "滚动", Kind::Chat),
+            comment(7., "顶部三", Kind::Chat),
+        ];
+        for index in [0, 1, 4] {
+            comments[index].mode = Mode::Top;
+        }
+        comments[2].mode = Mode::Bottom;
+        let trajectories = simulate(&mut comments, Options::default(), 15.);
+        golden("static_modes", &summary(&trajectories));
+    }
+
+    #[test]
+    fn export() {
+        let mut comments = vec![
+            comment(1., "导出", Kind::Chat),
+            comment(1.2, "同一时间", Kind::Chat),
+            comment(2., "顶部", Kind::Chat),
+            comment(30., "{\\fs200}不是标签", Kind::Chat),
+            comment(62.5, "一分钟后", Kind::Chat),
+        ];
+        comments[2].mode = Mode::Top;
+        let params = Params {
+            stretch: 1.,
+            speed: 1.,
+            osd_width: 1920.,
+            osd_height: 1080.,
+            ..Default::default()
+        };
+        // display_delay 不影响导出的时间
+        let options = Options {
+            display_delay: 3.,
+            ..Default::default()
+        };
+        golden("export", &export_ass(&comments, params, options));
+    }
+
+    #[test]
+    fn display_delay() {
+        let mut comments = vec![comment(1., "延迟显示", Kind::Chat)];
+        let options = Options {
+            display_delay: 2.,
+            ..Default::default()
+        };
+        let trajectories = simulate(&mut comments, options, 5.);
+        assert!((trajectories[0][0].0 - 3.).abs() < INTERVAL);
+    }
+}
@@ -1,53 +1,246 @@
+// Options::values 中的 json! 展开层数较多
+#![recursion_limit = "256"]
+
+#[cfg(feature = "network")]
+pub mod bilibili;
+#[cfg(feature = "c-api")]
+pub mod capi;
 pub mod danmaku;
+pub mod demo;
+#[cfg(feature = "network")]
+pub mod douyu;
 pub mod ffi;
+pub mod http;
+#[cfg(feature = "network")]
+pub mod huya;
+pub mod layout;
+#[cfg(feature = "network")]
+pub mod live;
 pub mod log;
 pub mod mpv;
 pub mod options;
+pub mod segments;
+pub mod series;
+pub mod state;
+pub mod stats;
+pub mod ytdl;
 
+#[cfg(feature = "network")]
+use crate::danmaku::search_keyword;
 use crate::{
-    danmaku::{get_danmaku,get_danmaku_byurl, Danmaku, Source, Status, StatusInner},
+    danmaku::{
+        clear_text, get_danmaku, get_danmaku_byhash, get_danmaku_byurl, load_xml, matched,
+        set_matched, supports_extractor, text, xml_sidecar, Ambiguous, Candidates, Danmaku,
+        LowConfidence, Matched, Source, Status, TooManyComments,
+    },
+    demo::generate,
     ffi::{
-        mpv_client_name, mpv_event_client_message, mpv_event_id, mpv_event_property, mpv_format,
-        mpv_handle, mpv_node, mpv_observe_property, mpv_wait_event, mpv_wakeup,
+        mpv_client_api_version, mpv_client_name, mpv_event_client_message, mpv_event_id,
+        mpv_event_property, mpv_format, mpv_handle, mpv_node, mpv_observe_property, mpv_wait_event,
+        mpv_wakeup,
+    },
+    http::{check_providers, dandanplay_credentials, Provider},
+    layout::{events, export_ass, frame_interval, layout, reset_status, Frame, Params},
+    log::{log_code, log_error, log_info},
+    mpv::{
+        af_metadata, command, expand_path, get_property_f64, get_property_string, hide_overlay,
+        osd_message, osd_message_for, osd_overlay, print_console, remove_overlay, set_osd_enabled,
+        set_property_f64, set_property_flag, set_property_string, set_vo_configured,
+        try_get_property_string, video_brightness,
+    },
+    options::{export_filter, import_filter, read_options, Filter, Options, Profile},
+    segments::{load_segments, remap},
+    series::{set_style, style},
+    state::{
+        content_key, fingerprint, has_watch_later, load_global, load_snapshot, load_state,
+        save_global, save_snapshot, save_state, FileState, GlobalState, Resume,
     },
-    log::{log_code, log_error},
-    mpv::{get_property_f64, get_property_string, osd_message, osd_overlay, remove_overlay},
-    options::{read_options, Filter, Options},
+    stats::{export_histogram, highlights, keywords},
+    ytdl::ytdl_info,
 };
 use anyhow::anyhow;
-use rand::{thread_rng, Rng};
+use rand::thread_rng;
 use std::{
     collections::HashSet,
-    ffi::CStr,
+    ffi::{c_char, CStr, CString},
+    fmt::Write,
+    fs,
+    ops::Range,
     os::raw::c_int,
+    path::Path,
     ptr::null_mut,
     slice::from_raw_parts,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, LazyLock,
     },
+    time::{Duration, Instant},
 };
-use tokio::{runtime::Builder, spawn, sync::Mutex};
-
-const MAX_DURATION: f64 = 12.;
-const INTERVAL: f64 = 0.005;
-const MIN_STEP: f64 = INTERVAL / MAX_DURATION;
-const MAX_STEP: f64 = MIN_STEP * 1.3;
+use tokio::{runtime::Builder, spawn, sync::Mutex, task::JoinHandle};
 
 pub static mut CTX: *mut mpv_handle = null_mut();
 pub static mut CLIENT_NAME: &str = "";
 
 static ENABLED: AtomicBool = AtomicBool::new(false);
 static COMMENTS: LazyLock<Mutex<Option<Vec<Danmaku>>>> = LazyLock::new(|| Mutex::new(None));
+// 支持的命令：名称、参数与说明
+const COMMANDS: &[(&str, &[&str], &str)] = &[
+    ("toggle-danmaku", &[], "toggle the danmaku visibility"),
+    ("danmaku-url", &["url"], "get danmaku by video webpage url"),
+    (
+        "danmaku-pick",
+        &["index"],
+        "choose one of the similar title search results listed on screen",
+    ),
+    ("danmaku-delay", &["seconds"], "add to the danmaku delay"),
+    (
+        "danmaku-stretch",
+        &["factor"],
+        "add to the timestamp stretch factor",
+    ),
+    ("danmaku-speed", &["factor"], "multiply the scrolling speed"),
+    (
+        "danmaku-font-size",
+        &["size"],
+        "set the font size, or add to it when signed",
+    ),
+    (
+        "danmaku-demo",
+        &["count", "duration"],
+        "generate synthetic danmaku",
+    ),
+    (
+        "danmaku-profile-cycle",
+        &[],
+        "switch to the next density preset",
+    ),
+    (
+        "danmaku-info",
+        &[],
+        "show the danmaku under the mouse cursor",
+    ),
+    (
+        "danmaku-block-under-cursor",
+        &[],
+        "block the sender of the danmaku under the mouse cursor",
+    ),
+    (
+        "danmaku-search",
+        &["text"],
+        "list loaded danmaku containing text in the console",
+    ),
+    (
+        "danmaku-filter-list",
+        &[],
+        "list the active filters in the console",
+    ),
+    (
+        "danmaku-filter-export",
+        &["path"],
+        "write the keyword, source and runtime blocklist filters as JSON",
+    ),
+    (
+        "danmaku-filter-import",
+        &["path"],
+        "merge filters exported by danmaku-filter-export into the runtime blocklist",
+    ),
+    (
+        "danmaku-console",
+        &[],
+        "open the console with the command prefix typed in",
+    ),
+    (
+        "danmaku-provider-status",
+        &[],
+        "check the connectivity of each provider",
+    ),
+    (
+        "danmaku-capabilities",
+        &["target", "message"],
+        "reply with this description as JSON",
+    ),
+    (
+        "danmaku-keywords",
+        &[],
+        "show the most frequent keywords of the loaded danmaku",
+    ),
+    (
+        "danmaku-chapters",
+        &["insert"],
+        "list highlights found in the danmaku, or insert them as chapters",
+    ),
+    (
+        "danmaku-export-histogram",
+        &["path"],
+        "write per-minute counts and top keywords as JSON",
+    ),
+    (
+        "danmaku-export-ass",
+        &["path"],
+        "write the loaded danmaku as an ASS subtitle file",
+    ),
+    (
+        "danmaku-legend",
+        &[],
+        "toggle the legend of the active sources and their on-screen counts",
+    ),
+    (
+        "danmaku-set",
+        &["option", "value"],
+        "set an option from script-opts/danmaku.conf at runtime",
+    ),
+    (
+        "danmaku-version",
+        &[],
+        "show the build and mpv client API version",
+    ),
+];
+
+// 最近一次渲染的弹幕位置，用于查找鼠标处的弹幕
+static FRAME: std::sync::Mutex<Option<Frame>> = std::sync::Mutex::new(None);
+static PENDING: LazyLock<Mutex<Option<Pending>>> = LazyLock::new(|| Mutex::new(None));
+// storm_speed 限速前的播放速度与限制的速度
+static CAPPED: std::sync::Mutex<Option<(f64, f64)>> = std::sync::Mutex::new(None);
+// 宿主支持 user-data 属性
+static USER_DATA: AtomicBool = AtomicBool::new(false);
+// 当前文件已确认访问网络
+#[cfg(feature = "network")]
+static CONSENTED: AtomicBool = AtomicBool::new(false);
+// 是否在右上角显示来源图例
+static LEGEND: AtomicBool = AtomicBool::new(false);
+// hide_while_seeking 时跳转期间为 true，不绘制弹幕
+static SEEKING: AtomicBool = AtomicBool::new(false);
+// 跳转结束后继续隐藏的时间，拖动进度条时会连续跳转
+const SEEK_GRACE: Duration = Duration::from_millis(500);
+// 两次定时渲染之间最多推进的实际时间，事件循环被阻塞较久时弹幕不会一次跳过太远
+const MAX_TICK: f64 = 0.1;
+// 上一次采样视频亮度的时间与结果
+static BRIGHTNESS: std::sync::Mutex<Option<(Instant, f64)>> = std::sync::Mutex::new(None);
+const BRIGHTNESS_INTERVAL: Duration = Duration::from_secs(2);
+// audio_reactive 时添加的音频滤镜标签，以及上一次读取响度的时间与结果
+const METER: &str = "danmaku-meter";
+static METER_ADDED: AtomicBool = AtomicBool::new(false);
+static LOUDNESS: std::sync::Mutex<Option<(Instant, f64)>> = std::sync::Mutex::new(None);
+const LOUDNESS_INTERVAL: Duration = Duration::from_millis(100);
+// storm_threshold 统计的时间范围
+const STORM_WINDOW: f64 = 5.;
 
-#[derive(Default, Clone, Copy)]
-struct Params {
-    delay: f64,
-    speed: f64,
-    osd_width: f64,
-    osd_height: f64,
+// 等待再次切换确认的操作
+enum Pending {
+    // 弹幕数量超过阈值或匹配置信度过低、等待确认下载的地址
+    Download(String),
+    // confirm_network 时等待确认访问网络
+    #[cfg(feature = "network")]
+    Network,
+    // 相似的搜索结果，等待按键选择，到达该时刻后使用第一个
+    // 截止时刻随列表一起保存，之前的列表被替换后不会再被自动选择
+    Pick(Vec<LowConfidence>, Instant),
 }
 
+// 选择搜索结果时临时绑定数字键的输入区段
+const PICK_SECTION: &str = "danmaku-pick";
+const PICK_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[no_mangle]
 extern "C" fn mpv_open_cplugin(ctx: *mut mpv_handle) -> c_int {
     unsafe {
@@ -67,8 +260,13 @@ async fn main() -> c_int {
         (c"script-opts", mpv_format::MPV_FORMAT_NODE),
         (c"pause", mpv_format::MPV_FORMAT_FLAG),
         (c"speed", mpv_format::MPV_FORMAT_DOUBLE),
-        (c"osd-width", mpv_format::MPV_FORMAT_DOUBLE),
-        (c"osd-height", mpv_format::MPV_FORMAT_DOUBLE),
+        (c"sub-delay", mpv_format::MPV_FORMAT_DOUBLE),
+        (c"osd-dimensions", mpv_format::MPV_FORMAT_NODE),
+        (c"display-fps", mpv_format::MPV_FORMAT_DOUBLE),
+        (c"time-pos", mpv_format::MPV_FORMAT_DOUBLE),
+        (c"seeking", mpv_format::MPV_FORMAT_FLAG),
+        (c"osd-level", mpv_format::MPV_FORMAT_INT64),
+        (c"vo-configured", mpv_format::MPV_FORMAT_FLAG),
     ] {
         let error = unsafe { mpv_observe_property(CTX, 0, name.as_ptr(), format) };
         if error < 0 {
@@ -77,41 +275,161 @@ async fn main() -> c_int {
         }
     }
 
-    let (options, filter) = read_options()
+    let (mut options, filter) = read_options()
         .map_err(|e| log_error(&e))
         .ok()
         .flatten()
         .unwrap_or_default();
+    audio_meter(options.audio_reactive);
+    if options.remember_enabled {
+        match load_global() {
+            Ok(state) => ENABLED.store(state.enabled, Ordering::SeqCst),
+            Err(error) => log_error(&error),
+        }
+    }
     let mut handle = spawn(async {});
-    let mut params = Params::default();
-    let mut pause = true;
+    let mut params = Params {
+        stretch: 1.,
+        pause: true,
+        ..Default::default()
+    };
+    let mut path = None;
+    // 已加载文件且尚未结束，空闲时不定时渲染
+    let mut playing = false;
+    // 保存状态使用的标识，默认为文件路径
+    let mut key = None;
+    // 上一次的 time-pos，用于暂停时逐帧播放
+    let mut last_pos = None;
+    // 上一次的 sub-delay，delay_follow_sub 时按其变化调整弹幕延迟
+    let mut sub_delay = None;
+    // 跳转结束后恢复显示的时间
+    let mut unhide: Option<Instant> = None;
+    // disable_at 当前所在的那一分钟已经关闭过弹幕、disable_after 在当前文件中已经关闭过弹幕
+    let mut bedtime = false;
+    let mut finished = false;
+    // 可被外部写入的 user-data 属性
+    let remote = ["enabled", "delay", "profile"].map(user_data);
+    // mpv 0.36 之前以及部分 libmpv 宿主没有 user-data 属性
+    USER_DATA.store(
+        try_get_property_string(c"user-data").is_some(),
+        Ordering::SeqCst,
+    );
+    mirror(params, options);
+    for (name, format) in remote.iter().zip([
+        mpv_format::MPV_FORMAT_FLAG,
+        mpv_format::MPV_FORMAT_DOUBLE,
+        mpv_format::MPV_FORMAT_STRING,
+    ]) {
+        if !USER_DATA.load(Ordering::SeqCst) {
+            break;
+        }
+        let name = CString::new(name.as_str()).unwrap();
+        let error = unsafe { mpv_observe_property(CTX, 0, name.as_ptr(), format) };
+        if error < 0 {
+            log_code(error);
+        }
+    }
+    // 上一次定时渲染的时间
+    let mut last_tick: Option<Instant> = None;
     loop {
-        let timeout = if !pause && ENABLED.load(Ordering::SeqCst) {
-            INTERVAL
+        let timeout = if playing && !params.pause && ENABLED.load(Ordering::SeqCst) {
+            last_tick.map_or(0., |tick| {
+                (frame_interval(params, options) - tick.elapsed().as_secs_f64()).max(0.)
+            })
         } else {
+            last_tick = None;
             -1.
         };
+        let pick = match &*PENDING.lock().await {
+            Some(Pending::Pick(_, deadline)) => Some(*deadline),
+            _ => None,
+        };
+        let timeout = match unhide.into_iter().chain(pick).min() {
+            Some(deadline) => {
+                let remaining = deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_secs_f64();
+                if timeout < 0. {
+                    remaining
+                } else {
+                    timeout.min(remaining)
+                }
+            }
+            None => timeout,
+        };
         let event = unsafe { &*mpv_wait_event(CTX, timeout) };
         match event.event_id {
             mpv_event_id::MPV_EVENT_SHUTDOWN => {
                 handle.abort();
+                save_resume(path.as_deref(), key.as_deref(), &filter).await;
+                if options.remember_enabled {
+                    let state = GlobalState {
+                        enabled: ENABLED.load(Ordering::SeqCst),
+                    };
+                    if let Err(error) = save_global(&state) {
+                        log_error(&error);
+                    }
+                }
                 return 0;
             }
             mpv_event_id::MPV_EVENT_FILE_LOADED => {
                 handle.abort();
                 *COMMENTS.lock().await = None;
-                params.delay = 0.;
+                clear_text();
+                *PENDING.lock().await = None;
+                #[cfg(feature = "network")]
+                live::DISCONNECTED.store(false, Ordering::SeqCst);
+                set_style(None);
+                #[cfg(feature = "network")]
+                CONSENTED.store(false, Ordering::SeqCst);
+                last_pos = None;
+                finished = false;
+                playing = true;
+                path = get_property_string(c"path");
+                key = path
+                    .as_deref()
+                    .map(|path| state_key(path, options.content_key));
+                let state = key
+                    .as_deref()
+                    .and_then(|key| load_state(key).map_err(|e| log_error(&e)).ok())
+                    .unwrap_or_default();
+                params.delay = state.delay;
+                params.stretch = state.stretch.unwrap_or(options.time_scale);
+                restore_speed(params);
+                if let Some(resume) = state.resume {
+                    if ENABLED.swap(resume.enabled, Ordering::SeqCst) && !resume.enabled {
+                        remove_overlay();
+                    }
+                    *filter.sources_rt.lock().await = resume
+                        .sources
+                        .map(|sources| sources.iter().map(|s| Source::from(s.as_str())).collect());
+                }
                 if ENABLED.load(Ordering::SeqCst) {
                     remove_overlay();
-                    handle = spawn(get(filter.clone()));
+                    if let Some(h) = load(&filter, options).await {
+                        handle = h;
+                    }
                 }
             }
-            mpv_event_id::MPV_EVENT_PLAYBACK_RESTART => {
-                if ENABLED.load(Ordering::SeqCst) {
-                    if let Some(comments) = &mut *COMMENTS.lock().await {
-                        reset_status(comments);
-                        render(comments, params, options);
-                    }
+            mpv_event_id::MPV_EVENT_END_FILE => {
+                save_resume(path.as_deref(), key.as_deref(), &filter).await;
+                // 播放列表结束或被清空后 mpv 可能一直空闲，不保留上一个文件的弹幕与请求
+                playing = false;
+                handle.abort();
+                *COMMENTS.lock().await = None;
+                clear_text();
+                if let Some(Pending::Pick(..)) = PENDING.lock().await.take() {
+                    command(&["disable-section", PICK_SECTION]);
+                }
+                #[cfg(feature = "network")]
+                live::DISCONNECTED.store(false, Ordering::SeqCst);
+                *FRAME.lock().unwrap() = None;
+                remove_overlay();
+            }
+            mpv_event_id::MPV_EVENT_PLAYBACK_RESTART if ENABLED.load(Ordering::SeqCst) => {
+                if let Some(comments) = &mut *COMMENTS.lock().await {
+                    reset_status(comments);
+                    render(comments, params, options);
                 }
             }
             mpv_event_id::MPV_EVENT_PROPERTY_CHANGE => 'a: {
@@ -121,14 +439,137 @@ async fn main() -> c_int {
                 }
                 let name = unsafe { CStr::from_ptr(data.name) };
                 if name == c"pause" {
-                    pause = unsafe { *(data.data as *mut c_int) } != 0;
-                } else if name == c"osd-width" {
-                    params.osd_width = unsafe { *(data.data as *mut f64) };
-                } else if name == c"osd-height" {
-                    params.osd_height = unsafe { *(data.data as *mut f64) };
+                    params.pause = unsafe { *(data.data as *mut c_int) } != 0;
+                } else if name == c"osd-dimensions" {
+                    let data = unsafe { &*(data.data as *mut mpv_node) };
+                    if data.format != mpv_format::MPV_FORMAT_NODE_MAP {
+                        break 'a;
+                    }
+                    let list = unsafe { &*data.u.list };
+                    let num = list.num.try_into().unwrap();
+                    let keys = unsafe { from_raw_parts(list.keys, num) };
+                    let values = unsafe { from_raw_parts(list.values, num) };
+                    for (key, value) in keys.iter().zip(values) {
+                        // 宽高与黑边为整数，par、aspect 为浮点数
+                        let value = match value.format {
+                            mpv_format::MPV_FORMAT_INT64 => unsafe { value.u.int64 as f64 },
+                            mpv_format::MPV_FORMAT_DOUBLE => unsafe { value.u.double_ },
+                            _ => continue,
+                        };
+                        match unsafe { CStr::from_ptr(key.cast()) }.to_bytes() {
+                            b"w" => params.osd_width = value,
+                            b"h" => params.osd_height = value,
+                            b"ml" => params.margin_left = value,
+                            b"mr" => params.margin_right = value,
+                            b"mt" => params.margin_top = value,
+                            b"mb" => params.margin_bottom = value,
+                            _ => (),
+                        }
+                    }
+                } else if name == c"osd-level" {
+                    set_osd_enabled(unsafe { *(data.data as *mut i64) } > 0);
+                } else if name == c"vo-configured" {
+                    let configured = unsafe { *(data.data as *mut c_int) } != 0;
+                    set_vo_configured(configured);
+                    // 暂停时不会定时渲染，补上没有视频输出期间跳过的弹幕层
+                    if configured && ENABLED.load(Ordering::SeqCst) {
+                        if let Some(comments) = &mut *COMMENTS.lock().await {
+                            render(comments, params, options);
+                        }
+                    }
+                } else if name == c"display-fps" {
+                    params.display_fps = unsafe { *(data.data as *mut f64) };
+                } else if name == c"seeking" {
+                    let seeking = unsafe { *(data.data as *mut c_int) } != 0;
+                    if seeking && options.hide_while_seeking {
+                        unhide = None;
+                        if !SEEKING.swap(true, Ordering::SeqCst) {
+                            hide_overlay();
+                        }
+                    } else if !seeking && SEEKING.load(Ordering::SeqCst) {
+                        unhide = Some(Instant::now() + SEEK_GRACE);
+                    }
+                } else if name == c"time-pos" {
+                    let pos = unsafe { *(data.data as *mut f64) };
+                    // 暂停时不进行定时渲染，逐帧播放（.）时按 time-pos 的变化推进一帧
+                    if let Some(step) = last_pos
+                        .map(|last| pos - last)
+                        .filter(|&step| params.pause && step > 0. && step < 1.)
+                    {
+                        if ENABLED.load(Ordering::SeqCst) {
+                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                render(comments, Params { step, ..params }, options);
+                            }
+                        }
+                    }
+                    if !filter.highlights.is_empty() && ENABLED.load(Ordering::SeqCst) {
+                        if let Some(last) = last_pos.filter(|&last| pos > last && pos - last < 1.) {
+                            if let Some(comments) = &*COMMENTS.lock().await {
+                                let delay = options.display_delay;
+                                cue(comments, &filter, params, last - delay..pos - delay);
+                            }
+                        }
+                    }
+                    // 每秒检查一次是否需要自动关闭弹幕
+                    if !last_pos.is_some_and(|last: f64| last.floor() == pos.floor()) {
+                        // 在 disable_at 的那一分钟内只关闭一次
+                        let at = options
+                            .disable_at
+                            .is_some_and(|at| local_time() == Some(at));
+                        let mut due = at && !bedtime;
+                        bedtime = at;
+                        if !finished && options.disable_after.is_some_and(|t| pos >= t * 60.) {
+                            finished = true;
+                            due = true;
+                        }
+                        if due && ENABLED.load(Ordering::SeqCst) {
+                            command(&[
+                                "script-message-to",
+                                unsafe { CLIENT_NAME },
+                                "toggle-danmaku",
+                            ]);
+                        }
+                    }
+                    last_pos = Some(pos);
+                } else if name.to_bytes() == remote[0].as_bytes() {
+                    let enabled = unsafe { *(data.data as *mut c_int) } != 0;
+                    if enabled != ENABLED.load(Ordering::SeqCst) {
+                        command(&[
+                            "script-message-to",
+                            unsafe { CLIENT_NAME },
+                            "toggle-danmaku",
+                        ]);
+                    }
+                } else if name.to_bytes() == remote[1].as_bytes() {
+                    let delay = unsafe { *(data.data as *mut f64) };
+                    if delay != params.delay {
+                        params.delay = delay;
+                        save(key.as_deref(), params, options);
+                        if ENABLED.load(Ordering::SeqCst) {
+                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                reset_status(comments);
+                                render(comments, params, options);
+                            }
+                        }
+                    }
+                } else if name.to_bytes() == remote[2].as_bytes() {
+                    let profile = unsafe { CStr::from_ptr(*(data.data as *mut *const c_char)) };
+                    if let Some(profile) = profile.to_str().ok().and_then(Profile::from_name) {
+                        if profile != options.profile {
+                            options.profile = profile;
+                            if ENABLED.load(Ordering::SeqCst) {
+                                if let Some(comments) = &mut *COMMENTS.lock().await {
+                                    render(comments, params, options);
+                                }
+                            }
+                        }
+                    }
                 } else if name == c"script-opts" {
                     let data = unsafe { &*(data.data as *mut mpv_node) };
-                    assert_eq!(data.format, mpv_format::MPV_FORMAT_NODE_MAP);
+                    // 部分 libmpv 宿主不以 NODE_MAP 提供 script-opts，此时只能使用 danmaku-set
+                    if data.format != mpv_format::MPV_FORMAT_NODE_MAP {
+                        break 'a;
+                    }
                     let list = unsafe { &*data.u.list };
                     if list.num == 0 {
                         break 'a;
@@ -137,58 +578,37 @@ async fn main() -> c_int {
                     let keys = unsafe { from_raw_parts(list.keys, num) };
                     let values = unsafe { from_raw_parts(list.values, num) };
                     for (key, value) in keys.iter().zip(values) {
-                        if unsafe { CStr::from_ptr(key.cast()) }
-                            .to_str()
-                            .is_ok_and(|key| {
-                                key == format!("{}-filter_source", unsafe { CLIENT_NAME })
-                            })
+                        if value.format == mpv_format::MPV_FORMAT_STRING
+                            && unsafe { CStr::from_ptr(key.cast()) }
+                                .to_str()
+                                .is_ok_and(|key| {
+                                    key == format!("{}-filter_source", unsafe { CLIENT_NAME })
+                                })
                         {
-                            assert_eq!(value.format, mpv_format::MPV_FORMAT_STRING);
                             match unsafe { CStr::from_ptr(value.u.string) }.to_str() {
-                                Ok(value) => {
-                                    *filter.sources_rt.lock().await = if value.is_empty() {
-                                        if let Some(comments) = &mut *COMMENTS.lock().await {
-                                            for comment in comments.iter_mut() {
-                                                comment.blocked =
-                                                    filter.sources.contains(&comment.source);
-                                                comment.status = Status::Uninitialized;
-                                            }
-                                            if ENABLED.load(Ordering::SeqCst) {
-                                                render(comments, params, options);
-                                            }
-                                        }
-                                        osd_message(&format!(
-                                            "Danmaku: blocked danmaku from {:?}",
-                                            filter.sources
-                                        ));
-                                        None
-                                    } else {
-                                        let sources = value
-                                            .split(',')
-                                            .map(Into::into)
-                                            .filter(|&s| s != Source::Unknown)
-                                            .collect::<HashSet<_>>();
-                                        if let Some(comments) = &mut *COMMENTS.lock().await {
-                                            for comment in comments.iter_mut() {
-                                                comment.blocked = sources.contains(&comment.source);
-                                                comment.status = Status::Uninitialized;
-                                            }
-                                            if ENABLED.load(Ordering::SeqCst) {
-                                                render(comments, params, options);
-                                            }
-                                        }
-                                        osd_message(&format!(
-                                            "Danmaku: blocked danmaku from {:?}",
-                                            sources
-                                        ));
-                                        Some(sources)
-                                    }
-                                }
+                                Ok(value) => set_sources(&filter, value, params, options).await,
                                 Err(error) => log_error(&error.into()),
                             }
                             break;
                         }
                     }
+                } else if name == c"sub-delay" {
+                    let delay = unsafe { *(data.data as *mut f64) };
+                    if let Some(last) = sub_delay.filter(|_| options.delay_follow_sub) {
+                        if delay != last {
+                            params.delay += delay - last;
+                            save(key.as_deref(), params, options);
+                            if ENABLED.load(Ordering::SeqCst) {
+                                if let Some(comments) = &mut *COMMENTS.lock().await {
+                                    reset_status(comments);
+                                    render(comments, params, options);
+                                }
+                            }
+                            osd_message(&delay_message(params.delay));
+                            mirror(params, options);
+                        }
+                    }
+                    sub_delay = Some(delay);
                 } else if name == c"speed" {
                     params.speed = unsafe { *(data.data as *mut f64) };
                 }
@@ -203,20 +623,55 @@ async fn main() -> c_int {
                 {
                     let arg1 = unsafe { CStr::from_ptr(*arg1) };
                     if arg1 == c"toggle-danmaku" {
-                        if ENABLED.fetch_not(Ordering::SeqCst) {
-                            handle.abort();
+                        let pending = PENDING.lock().await.take();
+                        if let Some(pending) = pending.filter(|_| ENABLED.load(Ordering::SeqCst)) {
+                            handle = match pending {
+                                Pending::Download(url) => {
+                                    spawn(get_byurl(filter.clone(), url, None, options))
+                                }
+                                #[cfg(feature = "network")]
+                                Pending::Network => {
+                                    CONSENTED.store(true, Ordering::SeqCst);
+                                    spawn(get(filter.clone(), options))
+                                }
+                                Pending::Pick(mut candidates, _) => {
+                                    command(&["disable-section", PICK_SECTION]);
+                                    spawn(get_byurl(
+                                        filter.clone(),
+                                        candidates.swap_remove(0).url,
+                                        limit(options),
+                                        options,
+                                    ))
+                                }
+                            };
+                            osd_message("Danmaku: loading");
+                        } else if ENABLED.fetch_not(Ordering::SeqCst) {
+                            // 直播弹幕在关闭期间不再接收，重新开启时重新连接
+                            if !handle.is_finished() {
+                                handle.abort();
+                                *COMMENTS.lock().await = None;
+                                clear_text();
+                                #[cfg(feature = "network")]
+                                live::DISCONNECTED.store(false, Ordering::SeqCst);
+                            }
                             remove_overlay();
+                            restore_speed(params);
                             osd_message("Danmaku: off");
                         } else {
                             match &mut *COMMENTS.lock().await {
                                 Some(comments) => {
                                     reset_status(comments);
                                     render(comments, params, options);
-                                    loaded(comments.iter().filter(|c| !c.blocked).count());
+                                    loaded(
+                                        comments.iter().filter(|c| !c.blocked).count(),
+                                        comments.iter().filter(|c| !c.blocked && c.new).count(),
+                                    );
                                 }
                                 None => {
-                                    handle = spawn(get(filter.clone()));
-                                    osd_message("Danmaku: on");
+                                    if let Some(h) = load(&filter, options).await {
+                                        handle = h;
+                                        osd_message("Danmaku: on");
+                                    }
                                 }
                             }
                         }
@@ -230,16 +685,14 @@ async fn main() -> c_int {
                                 {
                                     Some(seconds) => {
                                         params.delay += seconds;
+                                        save(key.as_deref(), params, options);
                                         if ENABLED.load(Ordering::SeqCst) {
                                             if let Some(comments) = &mut *COMMENTS.lock().await {
                                                 reset_status(comments);
                                                 render(comments, params, options);
                                             }
                                         }
-                                        osd_message(&format!(
-                                            "Danmaku delay: {:.0} ms",
-                                            params.delay * 1000.
-                                        ));
+                                        osd_message(&delay_message(params.delay));
                                     }
                                     None => {
                                         log_error(&anyhow!("command danmaku-delay: invalid time"))
@@ -250,30 +703,491 @@ async fn main() -> c_int {
                                 "command danmaku-delay: required argument seconds not set"
                             )),
                         }
-                    }else if arg1==c"danmaku-url" {
+                    } else if arg1 == c"danmaku-stretch" {
                         match args.first() {
-                            Some(&url) => {
-                                match unsafe { CStr::from_ptr(url) }
+                            Some(&factor) => {
+                                match unsafe { CStr::from_ptr(factor) }
                                     .to_str()
                                     .ok()
+                                    .and_then(|s| s.parse::<f64>().ok())
+                                    .filter(|f| params.stretch + f > 0.)
                                 {
-                                    Some(url) => {
-                                        if ENABLED.fetch_xor(true, Ordering::SeqCst) {
-                                            handle.abort();
-                                            *COMMENTS.lock().await = None;
-                                            remove_overlay();
-                                            handle = spawn(get_byurl(filter.clone(), url));
-                                            osd_message(&format!("Danmaku: on,{}", url));
-                                        } else {
-                                            handle = spawn(get_byurl(filter.clone(), url));
-                                            osd_message(&format!("Danmaku: on,{}", url));
+                                    Some(factor) => {
+                                        params.stretch += factor;
+                                        save(key.as_deref(), params, options);
+                                        if ENABLED.load(Ordering::SeqCst) {
+                                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                                reset_status(comments);
+                                                render(comments, params, options);
+                                            }
+                                        }
+                                        osd_message(&format!(
+                                            "Danmaku stretch: {:.4}x",
+                                            params.stretch
+                                        ));
+                                    }
+                                    None => log_error(&anyhow!(
+                                        "command danmaku-stretch: invalid factor"
+                                    )),
+                                }
+                            }
+                            None => log_error(&anyhow!(
+                                "command danmaku-stretch: required argument factor not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-speed" {
+                        match args.first() {
+                            Some(&factor) => {
+                                match unsafe { CStr::from_ptr(factor) }
+                                    .to_str()
+                                    .ok()
+                                    .and_then(|s| s.parse::<f64>().ok())
+                                    .filter(|f| *f > 0.)
+                                {
+                                    Some(factor) => {
+                                        options.speed *= factor;
+                                        if ENABLED.load(Ordering::SeqCst) {
+                                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                                reset_status(comments);
+                                                render(comments, params, options);
+                                            }
                                         }
+                                        osd_message(&format!(
+                                            "Danmaku speed: {:.2}x",
+                                            options.speed
+                                        ));
                                     }
                                     None => {
-                                        log_error(&anyhow!("command danmaku-url: invalid url"))
+                                        log_error(&anyhow!("command danmaku-speed: invalid factor"))
+                                    }
+                                }
+                            }
+                            None => log_error(&anyhow!(
+                                "command danmaku-speed: required argument factor not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-font-size" {
+                        match args.first() {
+                            Some(&size) => {
+                                // 带正负号时为增量，否则为新的字号
+                                match unsafe { CStr::from_ptr(size) }
+                                    .to_str()
+                                    .ok()
+                                    .and_then(|s| {
+                                        let size = s.parse::<f64>().ok()?;
+                                        Some(if s.starts_with(['+', '-']) {
+                                            options.font_size + size
+                                        } else {
+                                            size
+                                        })
+                                    })
+                                    .filter(|s| *s > 0.)
+                                {
+                                    Some(size) => {
+                                        options.font_size = size;
+                                        if ENABLED.load(Ordering::SeqCst) {
+                                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                                reset_status(comments);
+                                                render(comments, params, options);
+                                            }
+                                        }
+                                        osd_message(&format!(
+                                            "Danmaku font size: {}",
+                                            options.font_size
+                                        ));
+                                    }
+                                    None => log_error(&anyhow!(
+                                        "command danmaku-font-size: invalid size"
+                                    )),
+                                }
+                            }
+                            None => log_error(&anyhow!(
+                                "command danmaku-font-size: required argument size not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-demo" {
+                        let arg = |i: usize| {
+                            args.get(i)
+                                .and_then(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                        };
+                        match (arg(0), arg(1)) {
+                            (Some(count), Some(duration)) => match (
+                                count.parse::<usize>().ok().filter(|&n| n > 0),
+                                duration.parse::<f64>().ok().filter(|&d| d > 0.),
+                            ) {
+                                (Some(count), Some(duration)) => {
+                                    handle.abort();
+                                    *PENDING.lock().await = None;
+                                    #[cfg(feature = "network")]
+                                    live::DISCONNECTED.store(false, Ordering::SeqCst);
+                                    clear_text();
+                                    // 从当前播放位置开始生成
+                                    let pos = get_property_f64(c"time-pos").unwrap_or_default();
+                                    let mut comments = generate(count, pos, duration);
+                                    ENABLED.store(true, Ordering::SeqCst);
+                                    render(&mut comments, params, options);
+                                    *COMMENTS.lock().await = Some(comments);
+                                    osd_message(&format!(
+                                        "Generated {} demo danmaku comments",
+                                        thousands(count)
+                                    ));
+                                }
+                                _ => log_error(&anyhow!(
+                                    "command danmaku-demo: invalid count or duration"
+                                )),
+                            },
+                            _ => log_error(&anyhow!(
+                                "command danmaku-demo: required arguments count and duration not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-profile-cycle" {
+                        // 不重置已显示弹幕的位置，切换后新出现的弹幕按新的预设排布
+                        options.profile = options.profile.next();
+                        if ENABLED.load(Ordering::SeqCst) {
+                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                render(comments, params, options);
+                            }
+                        }
+                        osd_message(&format!("Danmaku profile: {}", options.profile.name()));
+                    } else if arg1 == c"danmaku-info" {
+                        let comments = COMMENTS.lock().await;
+                        match comments
+                            .as_deref()
+                            .filter(|_| ENABLED.load(Ordering::SeqCst))
+                            .and_then(|c| Some(&c[under_cursor(c, params)?]))
+                        {
+                            Some(comment) => {
+                                let text = text();
+                                let sender = comment.sender.get(&text);
+                                osd_message(&format!(
+                                    "{}\n{} | {} | {}",
+                                    comment.message.get(&text),
+                                    timestamp(comment.time),
+                                    comment.source.name(),
+                                    if sender.is_empty() {
+                                        "unknown sender"
+                                    } else {
+                                        sender
+                                    }
+                                ));
+                            }
+                            None => osd_message("Danmaku: no danmaku under the cursor"),
+                        }
+                    } else if arg1 == c"danmaku-block-under-cursor" {
+                        if let Some(comments) = &mut *COMMENTS.lock().await {
+                            if let Some(index) = ENABLED
+                                .load(Ordering::SeqCst)
+                                .then(|| under_cursor(comments, params))
+                                .flatten()
+                            {
+                                let mut blocklist = filter.blocklist.lock().await;
+                                let text = text();
+                                let sender = comments[index].sender.get(&text).to_string();
+                                let message = comments[index].message.get(&text).to_string();
+                                // 发送者未知时屏蔽相同内容的弹幕
+                                if sender.is_empty() {
+                                    blocklist.messages.insert(message.clone());
+                                } else {
+                                    blocklist.senders.insert(sender.clone());
+                                }
+                                for comment in comments.iter_mut() {
+                                    if blocklist.contains(
+                                        comment.sender.get(&text),
+                                        comment.message.get(&text),
+                                    ) {
+                                        comment.blocked = true;
+                                    }
+                                }
+                                drop(text);
+                                render(comments, params, options);
+                                osd_message(&if sender.is_empty() {
+                                    format!("Danmaku: blocked \"{}\"", message)
+                                } else {
+                                    format!("Danmaku: blocked sender {}", sender)
+                                });
+                            } else {
+                                osd_message("Danmaku: no danmaku under the cursor");
+                            }
+                        }
+                    } else if arg1 == c"danmaku-legend" {
+                        let shown = !LEGEND.fetch_xor(true, Ordering::SeqCst);
+                        if ENABLED.load(Ordering::SeqCst) {
+                            if let Some(comments) = &mut *COMMENTS.lock().await {
+                                render(comments, params, options);
+                            }
+                        }
+                        osd_message(if shown {
+                            "Danmaku: legend shown"
+                        } else {
+                            "Danmaku: legend hidden"
+                        });
+                    } else if arg1 == c"danmaku-console" {
+                        // 在控制台中预先输入命令前缀
+                        command(&[
+                            "script-message-to",
+                            "console",
+                            "type",
+                            &format!("script-message-to {} danmaku-", unsafe { CLIENT_NAME }),
+                        ]);
+                    } else if arg1 == c"danmaku-filter-list" {
+                        print_console(&filter_list(&filter).await);
+                    } else if arg1 == c"danmaku-search" {
+                        let keyword = args
+                            .iter()
+                            .filter_map(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                            .collect::<Vec<_>>()
+                            .join(" ");
+                        if keyword.is_empty() {
+                            log_error(&anyhow!(
+                                "command danmaku-search: required argument text not set"
+                            ));
+                        } else {
+                            match &*COMMENTS.lock().await {
+                                Some(comments) => print_console(&search(comments, &keyword)),
+                                None => osd_message("Danmaku: no danmaku loaded"),
+                            }
+                        }
+                    } else if arg1 == c"danmaku-keywords" {
+                        match &*COMMENTS.lock().await {
+                            Some(comments) => {
+                                let mut panel = "Danmaku: what people are saying".to_string();
+                                for (token, count) in keywords(comments, PANEL_KEYWORDS) {
+                                    _ = write!(panel, "\n{}  {}", token, thousands(count));
+                                }
+                                osd_message_for(&panel, 5000);
+                            }
+                            None => osd_message("Danmaku: no danmaku loaded"),
+                        }
+                    } else if arg1 == c"danmaku-chapters" {
+                        let insert = args
+                            .first()
+                            .is_some_and(|&arg| unsafe { CStr::from_ptr(arg) } == c"insert");
+                        match &*COMMENTS.lock().await {
+                            Some(comments) => {
+                                let highlights = highlights(comments)
+                                    .into_iter()
+                                    .map(|(time, marker)| {
+                                        (time * params.stretch + params.delay, marker)
+                                    })
+                                    .collect::<Vec<_>>();
+                                if highlights.is_empty() {
+                                    osd_message("Danmaku: no highlights found");
+                                } else if insert {
+                                    let n = insert_chapters(&highlights);
+                                    osd_message(&format!("Danmaku: {} chapters inserted", n));
+                                } else {
+                                    let mut lines = highlights
+                                        .iter()
+                                        .map(|(time, marker)| {
+                                            format!("[{}] {}", timestamp(*time), marker)
+                                        })
+                                        .collect::<Vec<_>>();
+                                    lines.push(format!(
+                                        "{} highlights, run danmaku-chapters insert to add them as chapters",
+                                        highlights.len()
+                                    ));
+                                    print_console(&lines);
+                                }
+                            }
+                            None => osd_message("Danmaku: no danmaku loaded"),
+                        }
+                    } else if arg1 == c"danmaku-export-histogram" {
+                        match args.first().map(|&arg| unsafe { CStr::from_ptr(arg) }) {
+                            Some(path) => match &*COMMENTS.lock().await {
+                                Some(comments) => match expand_path(path.to_bytes())
+                                    .and_then(|path| export_histogram(comments, &path))
+                                {
+                                    Ok(()) => osd_message(&format!(
+                                        "Danmaku: exported to {}",
+                                        path.to_string_lossy()
+                                    )),
+                                    Err(error) => log_error(&anyhow!(
+                                        "command danmaku-export-histogram: {}",
+                                        error
+                                    )),
+                                },
+                                None => osd_message("Danmaku: no danmaku loaded"),
+                            },
+                            None => log_error(&anyhow!(
+                                "command danmaku-export-histogram: required argument path not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-export-ass" {
+                        match args.first().map(|&arg| unsafe { CStr::from_ptr(arg) }) {
+                            Some(path) => match &*COMMENTS.lock().await {
+                                Some(comments) => {
+                                    match expand_path(path.to_bytes()).and_then(|expanded| {
+                                        let options = options.profile.apply(style(options));
+                                        Ok(fs::write(
+                                            expanded,
+                                            export_ass(comments, params, options),
+                                        )?)
+                                    }) {
+                                        Ok(()) => osd_message(&format!(
+                                            "Danmaku: exported to {}",
+                                            path.to_string_lossy()
+                                        )),
+                                        Err(error) => log_error(&anyhow!(
+                                            "command danmaku-export-ass: {}",
+                                            error
+                                        )),
+                                    }
+                                }
+                                None => osd_message("Danmaku: no danmaku loaded"),
+                            },
+                            None => log_error(&anyhow!(
+                                "command danmaku-export-ass: required argument path not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-filter-export" {
+                        match args.first().map(|&arg| unsafe { CStr::from_ptr(arg) }) {
+                            Some(path) => match expand_path(path.to_bytes()) {
+                                Ok(expanded) => match export_filter(&filter, &expanded).await {
+                                    Ok(()) => osd_message(&format!(
+                                        "Danmaku: exported to {}",
+                                        path.to_string_lossy()
+                                    )),
+                                    Err(error) => log_error(&anyhow!(
+                                        "command danmaku-filter-export: {}",
+                                        error
+                                    )),
+                                },
+                                Err(error) => {
+                                    log_error(&anyhow!("command danmaku-filter-export: {}", error))
+                                }
+                            },
+                            None => log_error(&anyhow!(
+                                "command danmaku-filter-export: required argument path not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-filter-import" {
+                        match args.first().map(|&arg| unsafe { CStr::from_ptr(arg) }) {
+                            Some(path) => match expand_path(path.to_bytes()) {
+                                Ok(expanded) => match import_filter(&filter, &expanded).await {
+                                    Ok(report) => {
+                                        refilter(&filter, params, options).await;
+                                        print_console(&report);
+                                    }
+                                    Err(error) => log_error(&anyhow!(
+                                        "command danmaku-filter-import: {}",
+                                        error
+                                    )),
+                                },
+                                Err(error) => {
+                                    log_error(&anyhow!("command danmaku-filter-import: {}", error))
+                                }
+                            },
+                            None => log_error(&anyhow!(
+                                "command danmaku-filter-import: required argument path not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-capabilities" {
+                        let arg = |i: usize| {
+                            args.get(i)
+                                .and_then(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                        };
+                        match arg(0) {
+                            Some(target) => {
+                                let reply = arg(1).unwrap_or("danmaku-capabilities-reply");
+                                let json = capabilities(options, params);
+                                command(&["script-message-to", target, reply, &json]);
+                            }
+                            None => log_error(&anyhow!(
+                                "command danmaku-capabilities: required argument target not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-set" {
+                        let arg = |i: usize| {
+                            args.get(i)
+                                .and_then(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                        };
+                        // 供无法设置 script-opts 的宿主使用，值可以为空
+                        match (arg(0), arg(1).unwrap_or_default()) {
+                            (Some("filter_source"), value) => {
+                                set_sources(&filter, value, params, options).await
+                            }
+                            (Some(option), value) => {
+                                if options.set(option, value) {
+                                    audio_meter(options.audio_reactive);
+                                    osd_message(&format!("Danmaku: {}={}", option, value));
+                                    if ENABLED.load(Ordering::SeqCst) {
+                                        if let Some(comments) = &mut *COMMENTS.lock().await {
+                                            render(comments, params, options);
+                                        }
                                     }
+                                } else {
+                                    log_error(&anyhow!(
+                                        "command danmaku-set: unknown option {}",
+                                        option
+                                    ));
                                 }
                             }
+                            (None, _) => log_error(&anyhow!(
+                                "command danmaku-set: required argument option not set"
+                            )),
+                        }
+                    } else if arg1 == c"danmaku-version" {
+                        let version = version();
+                        osd_message(&version);
+                        log_info(&version);
+                    } else if arg1 == c"danmaku-provider-status" {
+                        osd_message("Danmaku: checking providers");
+                        spawn(provider_status());
+                    } else if arg1 == c"danmaku-pick" {
+                        let index = args
+                            .first()
+                            .and_then(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().ok())
+                            .and_then(|arg| arg.parse::<usize>().ok());
+                        let mut pending = PENDING.lock().await;
+                        // 没有等待选择的结果时忽略，如已超时或已选择
+                        if let Some(Pending::Pick(candidates, _)) = &*pending {
+                            match index
+                                .and_then(|i| i.checked_sub(1))
+                                .and_then(|i| candidates.get(i))
+                            {
+                                Some(candidate) => {
+                                    command(&["disable-section", PICK_SECTION]);
+                                    handle = spawn(get_byurl(
+                                        filter.clone(),
+                                        candidate.url.clone(),
+                                        limit(options),
+                                        options,
+                                    ));
+                                    *pending = None;
+                                    osd_message("Danmaku: loading");
+                                }
+                                None => log_error(&anyhow!("command danmaku-pick: invalid index")),
+                            }
+                        }
+                    } else if arg1 == c"danmaku-url" {
+                        match args.first() {
+                            Some(&url) => match unsafe { CStr::from_ptr(url) }.to_str().ok() {
+                                Some(url) => {
+                                    let url = url.to_string();
+                                    if ENABLED.fetch_xor(true, Ordering::SeqCst) {
+                                        handle.abort();
+                                        *COMMENTS.lock().await = None;
+                                        clear_text();
+                                        remove_overlay();
+                                        osd_message(&format!("Danmaku: on,{}", url));
+                                        handle = spawn(get_byurl(
+                                            filter.clone(),
+                                            url,
+                                            limit(options),
+                                            options,
+                                        ));
+                                    } else {
+                                        osd_message(&format!("Danmaku: on,{}", url));
+                                        handle = spawn(get_byurl(
+                                            filter.clone(),
+                                            url,
+                                            limit(options),
+                                            options,
+                                        ));
+                                    }
+                                }
+                                None => log_error(&anyhow!("command danmaku-url: invalid url")),
+                            },
                             None => log_error(&anyhow!(
                                 "command danmaku-url: required argument url not set"
                             )),
@@ -281,167 +1195,849 @@ async fn main() -> c_int {
                     }
                 }
             }
-            mpv_event_id::MPV_EVENT_NONE => {
+            _ => (),
+        }
+        // 播放时 time-pos 每帧都会变化并唤醒事件循环，
+        // 按实际经过的时间而非唤醒次数定时渲染并推进滚动
+        if playing && !params.pause && ENABLED.load(Ordering::SeqCst) {
+            let interval = frame_interval(params, options);
+            let elapsed = last_tick.map(|tick| tick.elapsed().as_secs_f64());
+            if !elapsed.is_some_and(|elapsed| elapsed < interval) {
+                last_tick = Some(Instant::now());
+                if let Some(comments) = &mut *COMMENTS.lock().await {
+                    let step = elapsed.unwrap_or(interval).min(MAX_TICK) * params.speed;
+                    render(comments, Params { step, ..params }, options);
+                }
+            }
+        }
+        // 选择超时后使用第一个结果，置信度低于 confirm_below 时仍需确认
+        let expired = ENABLED.load(Ordering::SeqCst)
+            && matches!(
+                &*PENDING.lock().await,
+                Some(Pending::Pick(_, deadline)) if *deadline <= Instant::now()
+            );
+        if expired {
+            if let Some(Pending::Pick(mut candidates, _)) = PENDING.lock().await.take() {
+                command(&["disable-section", PICK_SECTION]);
+                let first = candidates.swap_remove(0);
+                if first.confidence < options.confirm_below {
+                    confirm_match(first).await;
+                } else {
+                    handle = spawn(get_byurl(
+                        filter.clone(),
+                        first.url,
+                        limit(options),
+                        options,
+                    ));
+                    osd_message("Danmaku: loading");
+                }
+            }
+        }
+        if unhide.is_some_and(|unhide| unhide <= Instant::now()) {
+            unhide = None;
+            SEEKING.store(false, Ordering::SeqCst);
+            if ENABLED.load(Ordering::SeqCst) {
                 if let Some(comments) = &mut *COMMENTS.lock().await {
                     render(comments, params, options);
                 }
             }
-            _ => (),
+        }
+        if matches!(
+            event.event_id,
+            mpv_event_id::MPV_EVENT_FILE_LOADED | mpv_event_id::MPV_EVENT_CLIENT_MESSAGE
+        ) {
+            mirror(params, options);
         }
     }
 }
 
-#[derive(Clone, Copy)]
-struct Row {
-    end: f64,
-    step: f64,
-}
-
 fn render(comments: &mut [Danmaku], params: Params, options: Options) {
+    if SEEKING.load(Ordering::SeqCst) {
+        return;
+    }
     let Some(pos) = get_property_f64(c"time-pos") else {
         return;
     };
-    let mut width = 1920.;
-    let mut height = 1080.;
-    let ratio = params.osd_width / params.osd_height;
-    if width / height < ratio {
-        height = width / ratio;
-    } else if width / height > ratio {
-        width = height * ratio;
-    }
-    let spacing = options.font_size / 10.;
-    let mut rows = vec![
-        Row {
-            end: 0.,
-            step: MIN_STEP,
-        };
-        ((height * (1. - options.reserved_space) / (options.font_size + spacing))
-            as usize)
-            .max(1)
-    ];
-
-    let mut danmaku = Vec::new();
-    let mut rng = thread_rng();
-    'it: for comment in comments.iter_mut().filter(|c| !c.blocked) {
-        let time = comment.time + params.delay;
-        if time > pos {
-            break;
-        }
+    let mut options = options.profile.apply(style(options));
+    if let Some(bright) = options.bright_transparency {
+        options.transparency = transparency(options.transparency, bright);
+    }
+    if options.audio_reactive {
+        options.border = border(options.border);
+    }
+    let frame = layout(comments, params, options, pos, &mut thread_rng());
+    let (width, height, spacing) = (frame.width, frame.height, frame.spacing);
+    let (left, top) = (frame.left, frame.top);
 
-        let status = match &mut comment.status {
-            Status::Status(status) => status,
-            Status::Overlapping => continue,
-            Status::Uninitialized => 'status: {
-                let ticks = (pos - time) / INTERVAL;
-                for (row, status) in rows.iter().enumerate() {
-                    if status.end < width - width * ticks * MIN_STEP {
-                        let max_step = if status.end == 0. {
-                            MAX_STEP
-                        } else {
-                            // 1 / max_step - ticks = status.end / width / status.step
-                            let max_step = 1. / (ticks + status.end / width / status.step);
-                            max_step.min(MAX_STEP)
-                        };
-                        let step = rng.gen_range(MIN_STEP..max_step);
-                        let x = width - width * ticks * step;
-                        break 'status comment.status.insert(StatusInner { x, row, step });
-                    }
-                }
-                if options.no_overlap {
-                    comment.status = Status::Overlapping;
-                    continue 'it;
-                }
-                let row = rows
-                    .iter()
-                    .enumerate()
-                    .min_by(|a, b| a.1.end.partial_cmp(&b.1.end).unwrap())
-                    .map(|(row, _)| row)
-                    .unwrap();
-                let step = MIN_STEP;
-                let x = width - width * ticks * step;
-                comment.status.insert(StatusInner { x, row, step })
-            }
-        };
-        if status.x + comment.count as f64 * options.font_size + spacing <= 0. {
-            continue;
+    let mut buf = events(comments, &frame, options);
+    #[cfg(feature = "network")]
+    if live::DISCONNECTED.load(Ordering::SeqCst) {
+        if !buf.is_empty() {
+            buf.push('\n');
         }
-        danmaku.push(format!(
-            "{{\\pos({},{})\\c&H{:x}{:x}{:x}&\\alpha&H{:x}\\fs{}\\bord1.5\\shad0\\b1\\q2}}{}",
-            status.x,
-            status.row as f64 * (options.font_size + spacing),
-            comment.b,
-            comment.g,
-            comment.r,
+        _ = write!(
+            buf,
+            "{{\\an3\\pos({},{})\\alpha&H{:x}\\fs{}\\bord1.5\\shad0\\q2}}Live chat disconnected",
+            frame.right - spacing,
+            frame.bottom - spacing,
             options.transparency,
-            options.font_size,
-            comment.message
-        ));
-
-        status.x -= width * status.step * params.speed * options.speed;
-        if let Some(row) = rows.get_mut(status.row) {
-            let end = status.x + comment.count as f64 * options.font_size + spacing;
-            if end / status.step > row.end / row.step {
-                *row = Row {
-                    end,
-                    step: status.step,
-                };
+            options.font_size / 2.
+        );
+    }
+    if storm(comments, params, options, pos) {
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        _ = write!(
+            buf,
+            "{{\\an7\\pos({},{})\\alpha&H{:x}\\fs{}\\bord1.5\\shad0\\q2}}▲",
+            left + spacing,
+            top + spacing,
+            options.transparency,
+            options.font_size / 2.
+        );
+    }
+    if LEGEND.load(Ordering::SeqCst) {
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str(&legend(comments, &frame, options));
+    }
+    osd_overlay(&buf, width as i64, height as i64, options.overlay_z);
+    *FRAME.lock().unwrap() = Some(frame);
+}
+
+// 画面平均亮度从 0.5 到 0.8 时，透明度从 transparency 逐渐变为 bright_transparency
+fn transparency(transparency: u8, bright: u8) -> u8 {
+    let mut sample = BRIGHTNESS.lock().unwrap();
+    if !sample.is_some_and(|(time, _)| time.elapsed() < BRIGHTNESS_INTERVAL) {
+        *sample = Some((Instant::now(), video_brightness().unwrap_or_default()));
+    }
+    let t = sample.map_or(0., |(_, b)| ((b - 0.5) / 0.3).clamp(0., 1.));
+    (transparency as f64 + (bright as f64 - transparency as f64) * t).round() as u8
+}
+
+// 添加或移除用于测量响度的 astats 滤镜
+fn audio_meter(enable: bool) {
+    if METER_ADDED.swap(enable, Ordering::SeqCst) == enable {
+        return;
+    }
+    if enable {
+        let filter = format!("@{}:lavfi=[astats=metadata=1:reset=1]", METER);
+        command(&["af", "add", &filter]);
+    } else {
+        command(&["af", "remove", &format!("@{}", METER)]);
+        *LOUDNESS.lock().unwrap() = None;
+    }
+}
+
+// 响度从 -40 dB 到 -10 dB 时，边框逐渐加粗到 1.5 倍，保留一位小数以免频繁更新弹幕层
+fn border(border: f64) -> f64 {
+    let mut sample = LOUDNESS.lock().unwrap();
+    if !sample.is_some_and(|(time, _)| time.elapsed() < LOUDNESS_INTERVAL) {
+        let rms = af_metadata(METER, "lavfi.astats.Overall.RMS_level")
+            .and_then(|v| v.parse::<f64>().ok())
+            .filter(|v| v.is_finite());
+        *sample = Some((Instant::now(), rms.unwrap_or(f64::MIN)));
+    }
+    let t = sample.map_or(0., |(_, rms)| ((rms + 40.) / 30.).clamp(0., 1.));
+    (border * (1. + t / 2.) * 10.).round() / 10.
+}
+
+// 在 OSD 上提示播放位置经过 range 时出现的含有 highlight 关键词的弹幕
+fn cue(comments: &[Danmaku], filter: &Filter, params: Params, range: Range<f64>) {
+    let time = |c: &Danmaku| c.time * params.stretch + params.delay;
+    let start = comments.partition_point(|c| time(c) <= range.start);
+    let text = text();
+    let messages = comments[start..]
+        .iter()
+        .take_while(|c| time(c) <= range.end)
+        .filter(|c| !c.blocked)
+        .map(|c| c.message.get(&text))
+        .filter(|message| {
+            filter
+                .highlights
+                .iter()
+                .any(|k| message.contains(k.as_str()))
+        })
+        .collect::<Vec<_>>();
+    if !messages.is_empty() {
+        osd_message(&format!("Danmaku: {}", messages.join("\n")));
+    }
+}
+
+// 同时显示弹幕延迟与字幕延迟
+fn delay_message(delay: f64) -> String {
+    match get_property_f64(c"sub-delay") {
+        Some(sub) => format!(
+            "Danmaku delay: {:.0} ms, sub delay: {:.0} ms",
+            delay * 1000.,
+            sub * 1000.
+        ),
+        None => format!("Danmaku delay: {:.0} ms", delay * 1000.),
+    }
+}
+
+// mpv 的 clock 属性，本地时间的时、分
+fn local_time() -> Option<(u8, u8)> {
+    let clock = try_get_property_string(c"clock")?;
+    let (h, m) = clock.split_once(':')?;
+    Some((h.parse().ok()?, m.parse().ok()?))
+}
+
+// 有未屏蔽弹幕的各来源及其当前显示的弹幕数量
+fn legend(comments: &[Danmaku], frame: &Frame, options: Options) -> String {
+    let mut active = [false; Source::ALL.len()];
+    for comment in comments.iter().filter(|c| !c.blocked) {
+        active[comment.source as usize] = true;
+    }
+    let mut counts = [0; Source::ALL.len()];
+    for index in frame
+        .scrolling
+        .iter()
+        .chain(&frame.fixed)
+        .map(|p| p.index)
+        .chain(frame.paid)
+    {
+        counts[comments[index].source as usize] += 1;
+    }
+    let mut buf = format!(
+        "{{\\an9\\pos({},{})\\alpha&H{:x}\\fs{}\\bord1.5\\shad0\\q2}}",
+        frame.right - frame.spacing,
+        frame.top + frame.spacing,
+        options.transparency,
+        options.font_size / 2.
+    );
+    for source in Source::ALL.into_iter().filter(|&s| active[s as usize]) {
+        if !buf.ends_with('}') {
+            buf.push_str("\\N");
+        }
+        _ = write!(
+            buf,
+            "{{\\c&H{}&}}■{{\\c&HFFFFFF&}} {} {}",
+            source.color(),
+            source.name(),
+            counts[source as usize]
+        );
+    }
+    buf
+}
+
+// 接下来几秒内的弹幕数量超过 storm_threshold 时返回 true，并按 storm_speed 限制播放速度
+fn storm(comments: &[Danmaku], params: Params, options: Options, pos: f64) -> bool {
+    if options.storm_threshold == 0 {
+        return false;
+    }
+    let time = |pos: f64| (pos - params.delay) / params.stretch;
+    let start = comments.partition_point(|c| c.time < time(pos));
+    let end = comments.partition_point(|c| c.time < time(pos + STORM_WINDOW));
+    let storm = comments[start..end.max(start)]
+        .iter()
+        .filter(|c| !c.blocked)
+        .count()
+        >= options.storm_threshold;
+    match options.storm_speed {
+        Some(cap) if storm => {
+            let mut capped = CAPPED.lock().unwrap();
+            if capped.is_none() && params.speed > cap {
+                *capped = Some((params.speed, cap));
+                set_property_f64("speed", cap);
             }
         }
+        _ if !storm => restore_speed(params),
+        _ => (),
     }
-    osd_overlay(&danmaku.join("\n"), width as i64, height as i64);
+    storm
 }
 
-async fn get(filter: Arc<Filter>) {
-    let Some(name) = get_property_string(c"media-title") else {
+// 限速期间用户修改过速度时不再恢复
+fn restore_speed(params: Params) {
+    if let Some((speed, cap)) = CAPPED.lock().unwrap().take() {
+        if params.speed == cap {
+            set_property_f64("speed", speed);
+        }
+    }
+}
+
+// 运行时修改屏蔽的来源，为空时恢复配置文件中的 filter_source
+async fn set_sources(filter: &Filter, value: &str, params: Params, options: Options) {
+    let sources = (!value.is_empty()).then(|| {
+        value
+            .split(',')
+            .map(Into::into)
+            .filter(|&s| s != Source::Unknown)
+            .collect::<HashSet<_>>()
+    });
+    osd_message(&format!(
+        "Danmaku: blocked danmaku from {:?}",
+        sources.as_ref().unwrap_or(&filter.sources)
+    ));
+    *filter.sources_rt.lock().await = sources;
+    refilter(filter, params, options).await;
+}
+
+// 按当前的来源与屏蔽列表重新标记已加载的弹幕
+async fn refilter(filter: &Filter, params: Params, options: Options) {
+    if let Some(comments) = &mut *COMMENTS.lock().await {
+        let sources = filter.sources_rt.lock().await;
+        let sources = sources.as_ref().unwrap_or(&filter.sources);
+        let blocklist = filter.blocklist.lock().await;
+        let text = text();
+        for comment in comments.iter_mut() {
+            comment.blocked = sources.contains(&comment.source)
+                || blocklist.contains(comment.sender.get(&text), comment.message.get(&text));
+            comment.status = Status::Uninitialized;
+        }
+        drop(text);
+        if ENABLED.load(Ordering::SeqCst) {
+            render(comments, params, options);
+        }
+    }
+}
+
+// 鼠标位置处的弹幕，mouse-pos 以 OSD 像素为单位
+fn under_cursor(comments: &[Danmaku], params: Params) -> Option<usize> {
+    let x = get_property_f64(c"mouse-pos/x")?;
+    let y = get_property_f64(c"mouse-pos/y")?;
+    let frame = FRAME.lock().unwrap();
+    let frame = frame.as_ref()?;
+    let scale = frame.width / params.osd_width;
+    frame.nearest(comments, x * scale, y * scale)
+}
+
+fn user_data(key: &str) -> String {
+    format!("user-data/{}/{}", unsafe { CLIENT_NAME }, key)
+}
+
+// 将当前状态写入 user-data 属性，供通过 JSON IPC 控制 mpv 的工具读取
+fn mirror(params: Params, options: Options) {
+    if !USER_DATA.load(Ordering::SeqCst) {
         return;
+    }
+    set_property_flag(&user_data("enabled"), ENABLED.load(Ordering::SeqCst));
+    set_property_f64(&user_data("delay"), params.delay);
+    set_property_string(&user_data("profile"), options.profile.name());
+}
+
+fn version() -> String {
+    let api = unsafe { mpv_client_api_version() };
+    format!(
+        "danmaku {} ({}), providers: {}, mpv client API {}.{}",
+        env!("CARGO_PKG_VERSION"),
+        env!("DANMAKU_GIT_HASH"),
+        if cfg!(feature = "network") {
+            Provider::ALL.map(Provider::name).join(", ")
+        } else {
+            "none (local-only build)".into()
+        },
+        api >> 16,
+        api & 0xffff,
+    )
+}
+
+fn capabilities(options: Options, params: Params) -> String {
+    serde_json::json!({
+        "commands": COMMANDS
+            .iter()
+            .map(|(name, args, description)| {
+                serde_json::json!({ "name": name, "args": args, "description": description })
+            })
+            .collect::<Vec<_>>(),
+        "options": options.values(),
+        "state": {
+            "enabled": ENABLED.load(Ordering::SeqCst),
+            "delay": params.delay,
+            "stretch": params.stretch,
+            "user_data": USER_DATA.load(Ordering::SeqCst),
+        },
+    })
+    .to_string()
+}
+
+async fn filter_list(filter: &Filter) -> Vec<String> {
+    let join = |items: Vec<&str>| {
+        if items.is_empty() {
+            "(none)".to_string()
+        } else {
+            items.join(", ")
+        }
     };
-    match get_danmaku(&name, filter).await {
-        Ok(danmaku) => {
-            let n = danmaku.iter().filter(|c| !c.blocked).count();
-            *COMMENTS.lock().await = Some(danmaku);
-            if ENABLED.load(Ordering::SeqCst) {
-                unsafe { mpv_wakeup(CTX) };
-                loaded(n);
-            }
+    let sources = filter.sources_rt.lock().await;
+    let sources = sources.as_ref().unwrap_or(&filter.sources);
+    let blocklist = filter.blocklist.lock().await;
+    vec![
+        format!(
+            "keywords: {}",
+            join(
+                filter
+                    .keywords
+                    .iter()
+                    .chain(&blocklist.keywords)
+                    .map(String::as_str)
+                    .collect()
+            )
+        ),
+        format!(
+            "patterns: {}",
+            join(
+                filter
+                    .patterns
+                    .patterns()
+                    .iter()
+                    .map(String::as_str)
+                    .collect()
+            )
+        ),
+        format!(
+            "sources: {}",
+            join(sources.iter().map(|s| s.name()).collect())
+        ),
+        format!(
+            "kinds: {}",
+            join(filter.kinds.iter().map(|k| k.name()).collect())
+        ),
+        format!(
+            "blocked senders: {}",
+            join(blocklist.senders.iter().map(String::as_str).collect())
+        ),
+        format!(
+            "blocked messages: {}",
+            join(blocklist.messages.iter().map(String::as_str).collect())
+        ),
+    ]
+}
+
+const MAX_SEARCH_RESULTS: usize = 500;
+// danmaku-keywords 显示的关键词数量
+const PANEL_KEYWORDS: usize = 10;
+
+fn search(comments: &[Danmaku], keyword: &str) -> Vec<String> {
+    let text = text();
+    let mut lines = comments
+        .iter()
+        .filter(|c| c.message.get(&text).contains(keyword))
+        .map(|c| {
+            format!(
+                "[{}] {} ({})",
+                timestamp(c.time),
+                c.message.get(&text),
+                c.source.name()
+            )
+        })
+        .collect::<Vec<_>>();
+    let n = lines.len();
+    lines.truncate(MAX_SEARCH_RESULTS);
+    lines.push(format!("{} danmaku matching \"{}\"", thousands(n), keyword));
+    lines
+}
+
+// 加入 chapter-list，已插入过的章节不重复添加
+fn insert_chapters(highlights: &[(f64, String)]) -> usize {
+    let mut chapters = try_get_property_string(c"chapter-list")
+        .and_then(|list| serde_json::from_str::<Vec<serde_json::Value>>(&list).ok())
+        .unwrap_or_default();
+    let n = chapters.len();
+    for (time, marker) in highlights {
+        let title = format!("Danmaku: {}", marker);
+        if !chapters.iter().any(|c| {
+            c["title"] == title.as_str()
+                && c["time"].as_f64().is_some_and(|t| (t - time).abs() < 1.)
+        }) {
+            chapters.push(serde_json::json!({ "title": title, "time": time }));
         }
-        Err(error) => {
-            log_error(&error);
-            if ENABLED.load(Ordering::SeqCst) {
-                osd_message(&format!("Danmaku: {}", error));
+    }
+    let n = chapters.len() - n;
+    chapters.sort_by(|a, b| {
+        let time = |c: &serde_json::Value| c["time"].as_f64().unwrap_or_default();
+        time(a).total_cmp(&time(b))
+    });
+    set_property_string(
+        "chapter-list",
+        &serde_json::Value::from(chapters).to_string(),
+    );
+    n
+}
+
+fn timestamp(time: f64) -> String {
+    let secs = time.max(0.) as u64;
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{:02}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+// confirm_network 时先显示将要发送给第三方的内容，再次切换确认后才访问网络
+async fn load(filter: &Arc<Filter>, options: Options) -> Option<JoinHandle<()>> {
+    // 没有视频轨或只有封面图时弹幕不会显示，不必下载
+    if try_get_property_string(c"current-tracks/video/albumart").as_deref() != Some("no") {
+        log_info("audio-only file, danmaku not loaded");
+        return None;
+    }
+    #[cfg(feature = "network")]
+    if options.confirm_network && !CONSENTED.load(Ordering::SeqCst) {
+        if let Some(request) = network_request(options) {
+            osd_message(&format!(
+                "Danmaku: send {}? toggle again to confirm",
+                request
+            ));
+            *PENDING.lock().await = Some(Pending::Network);
+            return None;
+        }
+    }
+    Some(spawn(get(filter.clone(), options)))
+}
+
+// 与 get 的判断顺序一致
+#[cfg(feature = "network")]
+fn network_request(options: Options) -> Option<String> {
+    if get_property_string(c"path").is_some_and(|p| xml_sidecar(&p).is_some()) {
+        return None;
+    }
+    let info = ytdl_info();
+    if let Some(url) = &info
+        .as_ref()
+        .and_then(|info| info.webpage_url.clone())
+        .or_else(|| get_property_string(c"path"))
+    {
+        if bilibili::Bilibili::detect(url).is_some() {
+            return Some(format!("{} to bilibili", url));
+        }
+        if douyu::Douyu::detect(url).is_some() {
+            return Some(format!("{} to douyu", url));
+        }
+        if huya::Huya::detect(url).is_some() {
+            return Some(format!("{} to huya", url));
+        }
+    }
+    if let Some(url) = info
+        .filter(|info| info.extractor.as_deref().is_some_and(supports_extractor))
+        .and_then(|info| info.webpage_url)
+    {
+        return Some(format!("{} to zxz", url));
+    }
+    let (keyword, episode) = search_keyword(
+        &get_property_string(c"media-title")?,
+        options.anonymize_query,
+    )
+    .ok()?;
+    let search = format!("\"{}\" (episode {}) to 360kan and zxz", keyword, episode);
+    match get_property_string(c"path")
+        .filter(|p| Path::new(p).is_file())
+        .filter(|_| dandanplay_credentials())
+    {
+        Some(_) if options.anonymize_query => Some(format!(
+            "the file hash and size to dandanplay, or if unmatched {}",
+            search
+        )),
+        Some(path) => Some(format!(
+            "the file hash, size and name \"{}\" to dandanplay, or if unmatched {}",
+            Path::new(&path)
+                .file_stem()
+                .map(|s| s.to_string_lossy())
+                .unwrap_or_default(),
+            search
+        )),
+        None => Some(search),
+    }
+}
+
+async fn get(filter: Arc<Filter>, options: Options) {
+    set_matched(None);
+    // 视频旁有同名的 .xml 弹幕文件时直接加载，不访问网络
+    if let Some(xml) = get_property_string(c"path").and_then(|p| xml_sidecar(&p)) {
+        set_matched(Some(Matched {
+            platform: "xml".into(),
+            episode: None,
+        }));
+        set_comments(load_xml(&xml, &filter).await, options).await;
+        return;
+    }
+    // 通过 yt-dlp 播放时使用其提供的原始网页地址，而不是解析后的媒体地址
+    let info = ytdl_info();
+    #[cfg(feature = "network")]
+    if let Some(url) = &info
+        .as_ref()
+        .and_then(|info| info.webpage_url.clone())
+        .or_else(|| get_property_string(c"path"))
+    {
+        if live::get_live(url, filter.clone()).await {
+            return;
+        }
+    }
+    if let Some(url) = info
+        .filter(|info| info.extractor.as_deref().is_some_and(supports_extractor))
+        .and_then(|info| info.webpage_url)
+    {
+        set_matched(Some(Matched::from_url(&url)));
+        get_byurl(filter, url, limit(options), options).await;
+        return;
+    }
+    // 设置了弹弹play 的应用 ID 与密钥时，本地文件先通过文件哈希匹配，没有唯一匹配时按标题搜索
+    if let Some(path) = get_property_string(c"path")
+        .filter(|p| Path::new(p).is_file())
+        .filter(|_| dandanplay_credentials())
+    {
+        match get_danmaku_byhash(
+            &path,
+            options.anonymize_query,
+            filter.clone(),
+            limit(options),
+        )
+        .await
+        {
+            Ok(Some(comments)) => {
+                set_comments(Ok(comments), options).await;
+                return;
+            }
+            Ok(None) => log_info("dandanplay: no exact match, searching by title"),
+            Err(error) if error.is::<TooManyComments>() => {
+                set_comments(Err(error), options).await;
+                return;
             }
+            Err(error) => log_error(&anyhow!("dandanplay: {}, searching by title", error)),
         }
     }
+    let Some(name) = get_property_string(c"media-title") else {
+        return;
+    };
+    set_comments(
+        get_danmaku(
+            &name,
+            options.anonymize_query,
+            options.confirm_below,
+            filter,
+            limit(options),
+        )
+        .await,
+        options,
+    )
+    .await;
 }
 
-async fn get_byurl (filter: Arc<Filter>, url: &str) {
-    match get_danmaku_byurl(url, filter).await {
-        Ok(danmaku) => {
+async fn get_byurl(filter: Arc<Filter>, url: String, limit: Option<u64>, options: Options) {
+    set_comments(get_danmaku_byurl(&url, filter, limit).await, options).await;
+}
+
+// 置信度低于 confirm_below 的匹配，再次切换确认后下载
+async fn confirm_match(matched: LowConfidence) {
+    osd_message(&format!(
+        "Danmaku: found {} episode {} ({:.0}% confidence), toggle again to accept",
+        matched.title,
+        matched.episode,
+        matched.confidence * 100.
+    ));
+    *PENDING.lock().await = Some(Pending::Download(matched.url));
+}
+
+async fn set_comments(result: anyhow::Result<Vec<Danmaku>>, options: Options) {
+    match result {
+        Ok(mut danmaku) => {
+            let path = get_property_string(c"path");
+            // 快照与其他按文件保存的状态一样以 state_key 标识
+            if let Some(path) = path.as_deref().filter(|_| options.track_new) {
+                mark_new(&state_key(path, options.content_key), &mut danmaku);
+            }
+            // 在 mark_new 之后映射，标识仍使用原视频中的时间
+            match path.as_deref().map(load_segments).transpose() {
+                Ok(segments) => remap(&mut danmaku, &segments.unwrap_or_default()),
+                Err(error) => log_error(&anyhow!("segments: {}", error)),
+            }
             let n = danmaku.iter().filter(|c| !c.blocked).count();
+            let new = danmaku.iter().filter(|c| !c.blocked && c.new).count();
             *COMMENTS.lock().await = Some(danmaku);
             if ENABLED.load(Ordering::SeqCst) {
                 unsafe { mpv_wakeup(CTX) };
-                loaded(n);
+                loaded(n, new);
             }
         }
-        Err(error) => {
-            log_error(&error);
-            if ENABLED.load(Ordering::SeqCst) {
-                osd_message(&format!("Danmaku: {}", error));
+        Err(error) => match error.downcast::<TooManyComments>() {
+            Ok(error) => {
+                osd_message(&format!(
+                    "Danmaku: about {} comments available, toggle again to load",
+                    thousands(error.count as usize)
+                ));
+                *PENDING.lock().await = Some(Pending::Download(error.url));
+            }
+            Err(error) if error.is::<LowConfidence>() => {
+                confirm_match(error.downcast::<LowConfidence>().unwrap()).await;
+            }
+            Err(error) if error.is::<Candidates>() => {
+                let error = error.downcast::<Candidates>().unwrap();
+                let mut lines = vec![format!(
+                    "Danmaku: similar results for episode {}, press a number to choose, loading 1 in {} s",
+                    error.episode,
+                    PICK_TIMEOUT.as_secs()
+                )];
+                let mut bindings = String::new();
+                for (i, candidate) in error.candidates.iter().enumerate() {
+                    lines.push(format!(
+                        "{} {} ({:.0}%)",
+                        i + 1,
+                        candidate.title,
+                        candidate.confidence * 100.
+                    ));
+                    _ = writeln!(
+                        bindings,
+                        "{} script-message-to {} danmaku-pick {}",
+                        i + 1,
+                        unsafe { CLIENT_NAME },
+                        i + 1
+                    );
+                }
+                osd_message_for(&lines.join("\n"), PICK_TIMEOUT.as_millis());
+                command(&["define-section", PICK_SECTION, &bindings, "force"]);
+                command(&["enable-section", PICK_SECTION]);
+                *PENDING.lock().await = Some(Pending::Pick(
+                    error.candidates,
+                    Instant::now() + PICK_TIMEOUT,
+                ));
+                // 事件循环按截止时刻重新计算等待时间
+                unsafe { mpv_wakeup(CTX) };
             }
+            Err(error) if error.is::<Ambiguous>() => {
+                let error = error.downcast::<Ambiguous>().unwrap();
+                osd_message(&format!(
+                    "Danmaku: {}, toggle again to load the first or add 上, 下 or 加更 to the title",
+                    error
+                ));
+                if let Some((_, url)) = error.candidates.into_iter().next() {
+                    *PENDING.lock().await = Some(Pending::Download(url));
+                }
+            }
+            Err(error) => {
+                // 丢弃下载中途已加入的部分弹幕
+                *COMMENTS.lock().await = None;
+                clear_text();
+                log_error(&error);
+                if ENABLED.load(Ordering::SeqCst) {
+                    osd_message(&format!("Danmaku: {}", error));
+                }
+            }
+        },
+    }
+}
+
+fn limit(options: Options) -> Option<u64> {
+    (options.count_threshold > 0).then_some(options.count_threshold)
+}
+
+async fn provider_status() {
+    let status = check_providers()
+        .await
+        .into_iter()
+        .map(|(provider, status)| format!("{}: {}", provider.name(), status))
+        .collect::<Vec<_>>();
+    for line in &status {
+        log_info(line);
+    }
+    osd_message(&status.join("\n"));
+}
+
+fn save(key: Option<&str>, params: Params, options: Options) {
+    update_state(key, |state| {
+        state.delay = params.delay;
+        // 与 time_scale 相同时不保存，之后修改 time_scale 对该文件同样生效
+        state.stretch = Some(params.stretch).filter(|&s| s != options.time_scale);
+    });
+}
+
+// 在 mpv 保存了 watch later 数据时记录显示状态与屏蔽的来源，否则清除
+async fn save_resume(path: Option<&str>, key: Option<&str>, filter: &Filter) {
+    let Some(path) = path else {
+        return;
+    };
+    let resume = if has_watch_later(path) {
+        Some(Resume {
+            enabled: ENABLED.load(Ordering::SeqCst),
+            sources: filter
+                .sources_rt
+                .lock()
+                .await
+                .as_ref()
+                .map(|sources| sources.iter().map(|s| s.name().to_string()).collect()),
+        })
+    } else {
+        None
+    };
+    update_state(key, |state| state.resume = resume);
+}
+
+fn update_state(key: Option<&str>, update: impl FnOnce(&mut FileState)) {
+    if let Some(key) = key {
+        let mut state = load_state(key).unwrap_or_default();
+        update(&mut state);
+        if let Err(error) = save_state(key, &state) {
+            log_error(&error);
         }
     }
 }
 
-fn reset_status(comments: &mut [Danmaku]) {
-    for comment in comments {
-        comment.status = Status::Uninitialized;
+// content_key 启用时本地文件以内容标识，网络地址与读取失败时仍使用路径
+fn state_key(path: &str, content: bool) -> String {
+    if content && Path::new(path).is_file() {
+        match content_key(path) {
+            Ok(key) => return key,
+            Err(error) => log_error(&anyhow!("content key: {}", error)),
+        }
     }
+    path.to_string()
 }
 
-fn loaded(n: usize) {
+// 同时显示来源平台与匹配到的分集，便于发现匹配错误
+fn loaded(n: usize, new: usize) {
+    let matched = matched()
+        .map(|m| {
+            let mut s = format!(" · {}", m.platform);
+            if let Some(episode) = m.episode {
+                s.push_str(" · ");
+                s.push_str(&episode);
+            }
+            s
+        })
+        .unwrap_or_default();
     osd_message(&format!(
-        "Loaded {} danmaku comment{}",
-        n,
-        if n > 1 { "s" } else { "" }
+        "Loaded {} danmaku comment{}{}{}",
+        thousands(n),
+        if n > 1 { "s" } else { "" },
+        matched,
+        if new > 0 {
+            format!(", {} new since last watch", new)
+        } else {
+            String::new()
+        }
     ));
 }
+
+// 与上次加载时保存的快照比较，标记新增的弹幕，并保存本次的快照
+fn mark_new(key: &str, comments: &mut [Danmaku]) {
+    let text = text();
+    let fingerprints = comments
+        .iter()
+        .map(|c| fingerprint(c.time, c.message.get(&text)))
+        .collect::<Vec<_>>();
+    match load_snapshot(key) {
+        Ok(Some(snapshot)) => {
+            for (comment, f) in comments.iter_mut().zip(&fingerprints) {
+                comment.new = !snapshot.contains(f);
+            }
+        }
+        Ok(None) => (),
+        Err(error) => log_error(&error),
+    }
+    if let Err(error) = save_snapshot(key, &fingerprints) {
+        log_error(&error);
+    }
+}
+
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut s = String::new();
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            s.push(',');
+        }
+        s.push(c);
+    }
+    s
+}
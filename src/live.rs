@@ -0,0 +1,211 @@
+use crate::{
+    bilibili::Bilibili,
+    danmaku::{Danmaku, Kind, Mode, Source, Status, Text},
+    douyu::Douyu,
+    http::{headers, Provider},
+    huya::Huya,
+    log::{log_debug, log_error},
+    mpv::{get_property_f64, osd_message},
+    options::Filter,
+    COMMENTS, ENABLED,
+};
+use anyhow::{anyhow, Result};
+use futures_util::{SinkExt, StreamExt};
+use std::{
+    future::Future,
+    mem::take,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    select,
+    time::{interval, sleep, sleep_until, Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, Message},
+};
+
+// 进入直播间时补上的最近弹幕在这段时间内依次显示
+const REPLAY_WINDOW: f64 = 10.;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+// 直播弹幕连接断开时为 true，用于在画面上显示提示
+pub static DISCONNECTED: AtomicBool = AtomicBool::new(false);
+
+pub struct LiveMessage {
+    pub message: String,
+    pub color: u32,
+    pub kind: Kind,
+}
+
+// 各直播平台的弹幕协议
+pub trait Protocol: Send {
+    const SOURCE: Source;
+    const PROVIDER: Provider;
+    const HEARTBEAT: Duration;
+
+    // 解析房间信息，返回 WebSocket 地址
+    fn prepare(&mut self) -> impl Future<Output = Result<String>> + Send;
+    fn handshake(&self) -> Vec<Message>;
+    fn heartbeat(&self) -> Message;
+    fn decode(&mut self, message: Message) -> Result<Vec<LiveMessage>>;
+
+    // 进入直播间前的最近弹幕，从早到晚排列，平台没有提供时为空
+    fn history(&mut self) -> impl Future<Output = Result<Vec<LiveMessage>>> + Send {
+        async { Ok(Vec::new()) }
+    }
+}
+
+// 播放地址为支持的直播间时持续接收弹幕，否则返回 false
+pub async fn get_live(url: &str, filter: Arc<Filter>) -> bool {
+    if let Some(room) = Bilibili::detect(url) {
+        *COMMENTS.lock().await = Some(Vec::new());
+        run(room, filter).await;
+    } else if let Some(room) = Douyu::detect(url) {
+        *COMMENTS.lock().await = Some(Vec::new());
+        run(room, filter).await;
+    } else if let Some(room) = Huya::detect(url) {
+        *COMMENTS.lock().await = Some(Vec::new());
+        run(room, filter).await;
+    } else {
+        return false;
+    }
+    true
+}
+
+// 持续接收直播弹幕，断线后以指数退避重连
+pub async fn run<P: Protocol>(mut protocol: P, filter: Arc<Filter>) {
+    let mut backoff = MIN_BACKOFF;
+    // 只在第一次连接时补上最近的弹幕，重连时已显示过
+    let mut backfill = true;
+    loop {
+        let mut received = false;
+        if let Err(error) = session(&mut protocol, &filter, &mut received, &mut backfill).await {
+            log_error(&anyhow!("live chat: {}", error));
+        }
+        if received {
+            backoff = MIN_BACKOFF;
+        }
+        DISCONNECTED.store(true, Ordering::SeqCst);
+        if ENABLED.load(Ordering::SeqCst) {
+            osd_message(&format!(
+                "Danmaku: live chat disconnected, reconnecting in {} s",
+                backoff.as_secs()
+            ));
+        }
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session<P: Protocol>(
+    protocol: &mut P,
+    filter: &Filter,
+    received: &mut bool,
+    backfill: &mut bool,
+) -> Result<()> {
+    let url = protocol.prepare().await?;
+    log_debug(&format!("live chat: connecting to {}", url));
+    let mut request = url.into_client_request()?;
+    request.headers_mut().extend(headers(P::PROVIDER));
+    let (mut stream, _) = connect_async(request).await?;
+    for message in protocol.handshake() {
+        stream.send(message).await?;
+    }
+    if DISCONNECTED.swap(false, Ordering::SeqCst) && ENABLED.load(Ordering::SeqCst) {
+        osd_message("Danmaku: live chat reconnected");
+    }
+    if take(backfill) {
+        match protocol.history().await {
+            Ok(messages) => append(messages, P::SOURCE, filter, REPLAY_WINDOW).await,
+            Err(error) => log_error(&anyhow!("live chat history: {}", error)),
+        }
+    }
+
+    let mut heartbeat = interval(P::HEARTBEAT);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    // 超过三个心跳周期没有收到任何数据则认为连接已失效
+    let mut deadline = Instant::now() + P::HEARTBEAT * 3;
+    loop {
+        select! {
+            _ = heartbeat.tick() => stream.send(protocol.heartbeat()).await?,
+            _ = sleep_until(deadline) => return Err(anyhow!("connection timed out")),
+            message = stream.next() => {
+                let message = match message {
+                    Some(message) => message?,
+                    None => return Ok(()),
+                };
+                deadline = Instant::now() + P::HEARTBEAT * 3;
+                match message {
+                    Message::Text(_) | Message::Binary(_) => {
+                        *received = true;
+                        match protocol.decode(message) {
+                            Ok(messages) => append(messages, P::SOURCE, filter, 0.).await,
+                            Err(error) => log_error(&anyhow!("live chat: {}", error)),
+                        }
+                    }
+                    Message::Close(_) => return Ok(()),
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+// 以当前播放位置作为弹幕时间加入弹幕列表，spread 大于 0 时依次分布在之后的 spread 秒内
+async fn append(messages: Vec<LiveMessage>, source: Source, filter: &Filter, spread: f64) {
+    if messages.is_empty() {
+        return;
+    }
+    let Some(pos) = get_property_f64(c"time-pos") else {
+        return;
+    };
+    let blocked = filter
+        .sources_rt
+        .lock()
+        .await
+        .as_ref()
+        .map(|s| s.contains(&source))
+        .unwrap_or_else(|| filter.sources.contains(&source));
+    let blocklist = filter.blocklist.lock().await;
+
+    let mut comments = COMMENTS.lock().await;
+    let comments = comments.get_or_insert_with(Vec::new);
+    let n = messages.len() as f64;
+    for (i, message) in messages.into_iter().enumerate() {
+        let time = pos + spread * i as f64 / n;
+        if filter.kinds.contains(&message.kind)
+            || filter
+                .keywords
+                .iter()
+                .any(|pat| message.message.contains(pat))
+            || filter.patterns.is_match(&message.message)
+        {
+            continue;
+        }
+        let index = comments.partition_point(|c| c.time <= time);
+        comments.insert(
+            index,
+            Danmaku {
+                blocked: blocked || blocklist.contains("", &message.message),
+                count: message.message.chars().count(),
+                message: Text::new(&message.message),
+                sender: Text::new(""),
+                time,
+                r: ((message.color >> 16) & 0xFF) as u8,
+                g: ((message.color >> 8) & 0xFF) as u8,
+                b: (message.color & 0xFF) as u8,
+                source,
+                kind: message.kind,
+                mode: Mode::Scroll,
+                status: Status::Uninitialized,
+                new: false,
+            },
+        );
+    }
+}
@@ -1,16 +1,88 @@
 use crate::{ffi::mpv_error_string, CLIENT_NAME};
 use anyhow::Error;
-use std::ffi::{c_int, CStr};
+use std::{
+    ffi::{c_int, CStr},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+};
 
-pub fn log_code(error: c_int) {
-    unsafe {
-        eprintln!(
-            "[{CLIENT_NAME}] {}",
-            CStr::from_ptr(mpv_error_string(error)).to_str().unwrap()
-        )
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+// 没有 stderr 时（如 Windows 上的 mpv.exe 窗口模式）写入的日志文件
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+pub struct LogFile {
+    pub path: PathBuf,
+    // 超过该大小时依次重命名为 .1、.2……后重新开始，最多保留 keep 个旧文件
+    pub max_size: u64,
+    pub keep: usize,
+}
+
+impl LogFile {
+    fn numbered(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&self) {
+        if !fs::metadata(&self.path).is_ok_and(|m| m.len() >= self.max_size) {
+            return;
+        }
+        if self.keep == 0 {
+            _ = fs::remove_file(&self.path);
+            return;
+        }
+        _ = fs::remove_file(self.numbered(self.keep));
+        for n in (1..self.keep).rev() {
+            _ = fs::rename(self.numbered(n), self.numbered(n + 1));
+        }
+        _ = fs::rename(&self.path, self.numbered(1));
     }
 }
 
+pub fn set_log_file(file: LogFile) {
+    *LOG_FILE.lock().unwrap() = Some(file);
+}
+
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::SeqCst);
+}
+
+fn log(line: &str) {
+    let name = unsafe { CLIENT_NAME };
+    eprintln!("[{name}] {line}");
+    if let Some(file) = &*LOG_FILE.lock().unwrap() {
+        file.rotate();
+        if let Ok(mut f) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file.path)
+        {
+            _ = writeln!(f, "[{name}] {line}");
+        }
+    }
+}
+
+pub fn log_code(error: c_int) {
+    log(unsafe { CStr::from_ptr(mpv_error_string(error)).to_str().unwrap() })
+}
+
 pub fn log_error(error: &Error) {
-    unsafe { eprintln!("[{CLIENT_NAME}] {error}") }
+    log(&error.to_string())
+}
+
+pub fn log_info(message: &str) {
+    log(message)
+}
+
+// 仅在 log_verbose=yes 时输出
+pub fn log_debug(message: &str) {
+    if VERBOSE.load(Ordering::SeqCst) {
+        log(message)
+    }
 }
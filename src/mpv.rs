@@ -4,23 +4,120 @@
 use crate::{
     ffi::{
         mpv_command, mpv_command_node, mpv_command_ret, mpv_error_string, mpv_format, mpv_free,
-        mpv_free_node_contents, mpv_get_property, mpv_node, mpv_node_list, u,
+        mpv_free_node_contents, mpv_get_property, mpv_node, mpv_node_list, mpv_set_property, u,
     },
+    log::log_info,
     log_code, CTX,
 };
 use anyhow::{anyhow, Result};
+#[cfg(unix)]
+use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
 use std::{
-    ffi::{c_char, CStr, CString},
+    ffi::{c_char, c_int, c_void, CStr, CString},
     mem::MaybeUninit,
+    path::PathBuf,
     ptr::{addr_of_mut, null, null_mut},
+    slice::from_raw_parts,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
 };
 
-pub fn osd_overlay(data: &str, width: i64, height: i64) {
-    let mut keys = [c"name", c"id", c"format", c"data", c"res_x", c"res_y"]
-        .map(|key| CString::from(key).into_raw());
+// 上一次发送的弹幕层内容，内容未变化时不再重复发送以免 libass 重新解析
+static LAST_OVERLAY: Mutex<Option<Overlay>> = Mutex::new(None);
+
+// osd-level 为 0 时 show-text 不会显示，提示改为输出到终端
+static OSD_ENABLED: AtomicBool = AtomicBool::new(true);
+// 没有视频输出时（--vo=null、--no-video、纯音频文件）弹幕层不会显示，不再发送
+static VO_CONFIGURED: AtomicBool = AtomicBool::new(true);
+
+struct Overlay {
+    data: String,
+    width: i64,
+    height: i64,
+    // 与其他脚本的 osd-overlay 重叠时的层级
+    z: i64,
+    hidden: bool,
+}
+
+// 弹幕内容等外部文本可能含有 NUL，去掉后再传给 mpv
+fn c_string(s: &str) -> CString {
+    CString::new(s).unwrap_or_else(|error| {
+        let mut bytes = error.into_vec();
+        bytes.retain(|&b| b != 0);
+        CString::new(bytes).unwrap()
+    })
+}
+
+// Unix 上的路径可以不是 UTF-8（如 GBK 编码的文件名），按字节转换以免丢失
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    return OsStr::from_bytes(bytes).into();
+    // Windows 上 mpv 的路径总是 UTF-8
+    #[cfg(not(unix))]
+    return String::from_utf8_lossy(bytes).into_owned().into();
+}
+
+pub fn set_osd_enabled(enabled: bool) {
+    OSD_ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn set_vo_configured(configured: bool) {
+    VO_CONFIGURED.store(configured, Ordering::SeqCst);
+}
+
+// 跳过时不更新 LAST_OVERLAY，其内容与 mpv 中的弹幕层保持一致
+pub fn osd_overlay(data: &str, width: i64, height: i64, z: i64) {
+    if !VO_CONFIGURED.load(Ordering::SeqCst) {
+        return;
+    }
+    let mut last = LAST_OVERLAY.lock().unwrap();
+    match &mut *last {
+        Some(last)
+            if last.data == data
+                && (last.width, last.height, last.z) == (width, height, z)
+                && !last.hidden =>
+        {
+            return
+        }
+        Some(last) => {
+            last.data.clear();
+            last.data.push_str(data);
+            (last.width, last.height, last.z, last.hidden) = (width, height, z, false);
+        }
+        None => {
+            *last = Some(Overlay {
+                data: data.to_string(),
+                width,
+                height,
+                z,
+                hidden: false,
+            })
+        }
+    }
+    send_overlay(last.as_ref().unwrap());
+}
+
+// 保留已发送的内容，只隐藏弹幕层
+pub fn hide_overlay() {
+    if !VO_CONFIGURED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Some(last) = LAST_OVERLAY.lock().unwrap().as_mut().filter(|o| !o.hidden) {
+        last.hidden = true;
+        send_overlay(last);
+    }
+}
+
+fn send_overlay(overlay: &Overlay) {
+    let mut keys = [
+        c"name", c"id", c"format", c"data", c"res_x", c"res_y", c"z", c"hidden",
+    ]
+    .map(|key| CString::from(key).into_raw());
     let value1 = CString::from(c"osd-overlay").into_raw();
     let value3 = CString::from(c"ass-events").into_raw();
-    let value4 = CString::new(data).unwrap().into_raw();
+    let value4 = c_string(&overlay.data).into_raw();
     let mut values = [
         mpv_node {
             format: mpv_format::MPV_FORMAT_STRING,
@@ -40,11 +137,25 @@ pub fn osd_overlay(data: &str, width: i64, height: i64) {
         },
         mpv_node {
             format: mpv_format::MPV_FORMAT_INT64,
-            u: u { int64: width },
+            u: u {
+                int64: overlay.width,
+            },
+        },
+        mpv_node {
+            format: mpv_format::MPV_FORMAT_INT64,
+            u: u {
+                int64: overlay.height,
+            },
         },
         mpv_node {
             format: mpv_format::MPV_FORMAT_INT64,
-            u: u { int64: height },
+            u: u { int64: overlay.z },
+        },
+        mpv_node {
+            format: mpv_format::MPV_FORMAT_FLAG,
+            u: u {
+                flag: overlay.hidden.into(),
+            },
         },
     ];
     assert_eq!(keys.len(), values.len());
@@ -74,6 +185,7 @@ pub fn osd_overlay(data: &str, width: i64, height: i64) {
 }
 
 pub fn remove_overlay() {
+    *LAST_OVERLAY.lock().unwrap() = None;
     let mut keys = [c"name", c"id", c"format", c"data"].map(|key| CString::from(key).into_raw());
     let value1 = CString::from(c"osd-overlay").into_raw();
     let value3 = CString::from(c"none").into_raw();
@@ -140,7 +252,42 @@ pub fn get_property_f64(name: &CStr) -> Option<f64> {
     }
 }
 
-pub fn get_property_string(name: &CStr) -> Option<String> {
+fn set_property(name: &str, format: mpv_format, data: *mut c_void) {
+    let name = c_string(name);
+    let error = unsafe { mpv_set_property(CTX, name.as_ptr(), format, data) };
+    if error < 0 {
+        log_code(error);
+    }
+}
+
+pub fn set_property_f64(name: &str, mut value: f64) {
+    set_property(
+        name,
+        mpv_format::MPV_FORMAT_DOUBLE,
+        addr_of_mut!(value).cast(),
+    );
+}
+
+pub fn set_property_flag(name: &str, value: bool) {
+    let mut value = c_int::from(value);
+    set_property(
+        name,
+        mpv_format::MPV_FORMAT_FLAG,
+        addr_of_mut!(value).cast(),
+    );
+}
+
+pub fn set_property_string(name: &str, value: &str) {
+    let value = c_string(value);
+    let mut ptr = value.as_ptr();
+    set_property(
+        name,
+        mpv_format::MPV_FORMAT_STRING,
+        addr_of_mut!(ptr).cast(),
+    );
+}
+
+fn property_string(name: &CStr) -> Result<String, c_int> {
     let mut data = unsafe { MaybeUninit::<*mut c_char>::uninit().assume_init() };
     let error = unsafe {
         mpv_get_property(
@@ -151,45 +298,166 @@ pub fn get_property_string(name: &CStr) -> Option<String> {
         )
     };
     if error < 0 {
-        log_code(error);
-        None
+        Err(error)
     } else {
-        let value = unsafe { CStr::from_ptr(data) }
-            .to_str()
-            .unwrap()
-            .to_string();
+        // 非 UTF-8 的内容以替换字符表示，不影响从其余部分匹配剧集
+        let value =
+            String::from_utf8_lossy(unsafe { CStr::from_ptr(data) }.to_bytes()).into_owned();
         unsafe { mpv_free(data.cast()) };
-        Some(value)
+        Ok(value)
     }
 }
 
-pub fn expand_path(path: &str) -> Result<String> {
+pub fn get_property_string(name: &CStr) -> Option<String> {
+    property_string(name).map_err(log_code).ok()
+}
+
+// 用于可能不存在的属性，失败时不记录错误
+pub fn try_get_property_string(name: &CStr) -> Option<String> {
+    property_string(name).ok()
+}
+
+// 参数可以是客户端消息中的原始字节，结果保留非 UTF-8 的路径
+pub fn expand_path(path: impl AsRef<[u8]>) -> Result<PathBuf> {
+    let arg2 = CString::new(path.as_ref()).map_err(|_| anyhow!("path contains NUL"))?;
     unsafe {
-        let arg2 = CString::new(path).unwrap();
         let mut args = [c"expand-path".as_ptr(), arg2.as_ptr(), null()];
         let mut result = MaybeUninit::<mpv_node>::uninit().assume_init();
         let error = mpv_command_ret(CTX, args.as_mut_ptr(), addr_of_mut!(result));
         if error < 0 {
             return Err(anyhow!(
                 "{}",
-                CStr::from_ptr(mpv_error_string(error)).to_str().unwrap()
+                CStr::from_ptr(mpv_error_string(error)).to_string_lossy()
             ));
         }
-        assert_eq!(result.format, mpv_format::MPV_FORMAT_STRING);
-        let path = CStr::from_ptr(result.u.string)
-            .to_str()
-            .unwrap()
-            .to_string();
+        if result.format != mpv_format::MPV_FORMAT_STRING {
+            mpv_free_node_contents(addr_of_mut!(result));
+            return Err(anyhow!("expand-path returned no string"));
+        }
+        let path = path_from_bytes(CStr::from_ptr(result.u.string).to_bytes());
         mpv_free_node_contents(addr_of_mut!(result));
         Ok(path)
     }
 }
 
+pub fn command(args: &[&str]) {
+    let args = args.iter().map(|arg| c_string(arg)).collect::<Vec<_>>();
+    let mut ptrs = args
+        .iter()
+        .map(|arg| arg.as_ptr())
+        .chain([null()])
+        .collect::<Vec<_>>();
+    let error = unsafe { mpv_command(CTX, ptrs.as_mut_ptr()) };
+    if error < 0 {
+        log_code(error);
+    }
+}
+
+// 输出到 console.lua 中并打开控制台，便于滚动查看较长的结果
+pub fn print_console(lines: &[String]) {
+    for line in lines {
+        command(&["print-text", line]);
+    }
+    command(&["script-message-to", "console", "enable"]);
+}
+
 pub fn osd_message(text: &str) {
-    let arg2 = CString::new(text).unwrap();
+    if !OSD_ENABLED.load(Ordering::SeqCst) {
+        return log_info(text);
+    }
+    let arg2 = c_string(text);
     let mut args = [c"show-text".as_ptr(), arg2.as_ptr(), null()];
     let error = unsafe { mpv_command(CTX, args.as_mut_ptr()) };
     if error < 0 {
         log_code(error);
     }
 }
+
+// 显示 duration 毫秒，用于较长的列表
+pub fn osd_message_for(text: &str, duration: u128) {
+    if OSD_ENABLED.load(Ordering::SeqCst) {
+        command(&["show-text", text, &duration.to_string()]);
+    } else {
+        log_info(text);
+    }
+}
+
+// 截取当前视频帧（不含字幕与 OSD），返回间隔采样得到的平均亮度，范围 0 到 1
+pub fn video_brightness() -> Option<f64> {
+    unsafe {
+        let mut args = [c"screenshot-raw".as_ptr(), c"video".as_ptr(), null()];
+        let mut result = MaybeUninit::<mpv_node>::uninit().assume_init();
+        // 没有视频时失败，不记录错误
+        if mpv_command_ret(CTX, args.as_mut_ptr(), addr_of_mut!(result)) < 0 {
+            return None;
+        }
+        let (mut w, mut h, mut stride, mut data) = (0, 0, 0, None);
+        if result.format == mpv_format::MPV_FORMAT_NODE_MAP {
+            let list = &*result.u.list;
+            for i in 0..list.num as usize {
+                let value = &*list.values.add(i);
+                match CStr::from_ptr(*list.keys.add(i)).to_bytes() {
+                    b"w" => w = value.u.int64 as usize,
+                    b"h" => h = value.u.int64 as usize,
+                    b"stride" => stride = value.u.int64 as usize,
+                    b"data" if value.format == mpv_format::MPV_FORMAT_BYTE_ARRAY => {
+                        let ba = &*value.u.ba;
+                        data = Some(from_raw_parts(ba.data as *const u8, ba.size));
+                    }
+                    _ => (),
+                }
+            }
+        }
+        // 格式为 bgr0，每个像素 4 字节
+        let brightness = data
+            .filter(|data| w > 0 && h > 0 && data.len() >= stride * h)
+            .map(|data| {
+                let (mut sum, mut n) = (0., 0.);
+                for y in (0..h).step_by(16) {
+                    for x in (0..w).step_by(16) {
+                        let p = &data[y * stride + x * 4..];
+                        sum += 0.114 * p[0] as f64 + 0.587 * p[1] as f64 + 0.299 * p[2] as f64;
+                        n += 1.;
+                    }
+                }
+                sum / n / 255.
+            });
+        mpv_free_node_contents(addr_of_mut!(result));
+        brightness
+    }
+}
+
+// af-metadata/<label> 中的一项，滤镜尚未输出时返回 None
+pub fn af_metadata(label: &str, key: &str) -> Option<String> {
+    let name = c_string(&format!("af-metadata/{}", label));
+    unsafe {
+        let mut result = MaybeUninit::<mpv_node>::uninit().assume_init();
+        if mpv_get_property(
+            CTX,
+            name.as_ptr(),
+            mpv_format::MPV_FORMAT_NODE,
+            addr_of_mut!(result).cast(),
+        ) < 0
+        {
+            return None;
+        }
+        let mut value = None;
+        if result.format == mpv_format::MPV_FORMAT_NODE_MAP {
+            let list = &*result.u.list;
+            for i in 0..list.num as usize {
+                let node = &*list.values.add(i);
+                if node.format == mpv_format::MPV_FORMAT_STRING
+                    && CStr::from_ptr(*list.keys.add(i)).to_bytes() == key.as_bytes()
+                {
+                    value = CStr::from_ptr(node.u.string)
+                        .to_str()
+                        .ok()
+                        .map(String::from);
+                    break;
+                }
+            }
+        }
+        mpv_free_node_contents(addr_of_mut!(result));
+        value
+    }
+}
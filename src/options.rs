@@ -1,10 +1,22 @@
-use crate::{danmaku::Source, log::log_error, mpv::expand_path, CLIENT_NAME};
+use crate::{
+    danmaku::{set_rewrites, Kind, Source, SITES},
+    http::{
+        set_fixtures, set_headers, set_max_bandwidth, set_max_requests, Fixtures, Headers, Provider,
+    },
+    log::{log_error, set_log_file, set_verbose, LogFile},
+    mpv::expand_path,
+    state::set_state_dir,
+    CLIENT_NAME,
+};
 use anyhow::{anyhow, Result};
-use serde::Deserialize;
+use regex::{Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::{
-    collections::HashSet,
-    fs::File,
+    collections::{HashMap, HashSet},
+    fs::{write, File},
     io::{BufRead, BufReader, ErrorKind},
+    path::Path,
     sync::Arc,
 };
 use tokio::sync::Mutex;
@@ -16,13 +28,141 @@ struct BilibiliFilterRule {
     opened: bool,
 }
 
+// 可在播放中切换的密度预设
+#[derive(Clone, Copy, PartialEq)]
+pub enum Profile {
+    Sparse,
+    Normal,
+    Full,
+}
+
+impl Profile {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sparse" => Some(Profile::Sparse),
+            "normal" => Some(Profile::Normal),
+            "full" => Some(Profile::Full),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::Sparse => "sparse",
+            Profile::Normal => "normal",
+            Profile::Full => "full",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            Profile::Sparse => Profile::Normal,
+            Profile::Normal => Profile::Full,
+            Profile::Full => Profile::Sparse,
+        }
+    }
+
+    // normal 即配置文件中的选项
+    pub fn apply(self, mut options: Options) -> Options {
+        match self {
+            Profile::Sparse => {
+                options.reserved_space = options.reserved_space.max(0.5);
+                options.no_overlap = true;
+            }
+            Profile::Normal => (),
+            Profile::Full => {
+                options.reserved_space = 0.;
+                options.reserved_pixels = None;
+                options.no_overlap = false;
+            }
+        }
+        options
+    }
+}
+
+// 滚动弹幕的运动方式
+#[derive(Clone, Copy, PartialEq)]
+pub enum Easing {
+    Linear,
+    // 接近左边缘时逐渐减速，便于读完速度较快的弹幕
+    EaseOut,
+}
+
+impl Easing {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "linear" => Some(Easing::Linear),
+            "ease-out" => Some(Easing::EaseOut),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Easing::Linear => "linear",
+            Easing::EaseOut => "ease-out",
+        }
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Options {
     pub font_size: f64,
     pub transparency: u8,
+    pub bright_transparency: Option<u8>,
+    pub border: f64,
+    pub audio_reactive: bool,
     pub reserved_space: f64,
+    // 以 OSD 像素为单位的保留高度，与 reserved_space 取较大者
+    pub reserved_pixels: Option<f64>,
+    // 弹幕层四周内缩的比例，用于有过扫描的电视
+    pub safe_area: f64,
+    pub side_panels: bool,
+    pub two_columns: bool,
     pub speed: f64,
+    pub easing: Easing,
     pub no_overlap: bool,
+    // 同时显示的弹幕数上限，0 表示不限制
+    pub max_onscreen: usize,
+    pub lane_gap: Option<f64>,
+    pub unique_priority: bool,
+    pub max_lateness: f64,
+    // 没有保存时间轴缩放的文件使用的缩放系数
+    pub time_scale: f64,
+    pub delay_follow_sub: bool,
+    // 每条弹幕推迟显示的秒数，与时间轴的 delay 无关
+    pub display_delay: f64,
+    pub min_display_time: Option<f64>,
+    pub max_display_time: Option<f64>,
+    // 各来源占用的行区间序号，0 表示使用所有行
+    pub source_lanes: [u8; Source::Unknown as usize + 1],
+    pub pixel_snap: bool,
+    pub hide_while_seeking: bool,
+    pub overlay_z: i64,
+    pub paid_lane: bool,
+    pub paid_duration: f64,
+    pub paid_color: u32,
+    // 顶部与底部固定弹幕的显示时间
+    pub static_duration: f64,
+    pub count_threshold: u64,
+    pub storm_threshold: usize,
+    pub storm_speed: Option<f64>,
+    pub remember_enabled: bool,
+    // 到达该时刻（本地时间的时、分）时自动关闭弹幕
+    pub disable_at: Option<(u8, u8)>,
+    // 播放位置超过该分钟数时自动关闭弹幕
+    pub disable_after: Option<f64>,
+    pub track_new: bool,
+    // 本地文件的保存状态以内容而非路径标识
+    pub content_key: bool,
+    pub highlight_new: bool,
+    // 不访问网络的版本没有需要确认的请求
+    #[cfg(feature = "network")]
+    pub confirm_network: bool,
+    pub anonymize_query: bool,
+    // 自动匹配的置信度低于该值时需要确认，0 表示不确认
+    pub confirm_below: f64,
+    pub profile: Profile,
 }
 
 impl Default for Options {
@@ -30,22 +170,473 @@ impl Default for Options {
         Self {
             font_size: 40.,
             transparency: 0x30,
+            bright_transparency: None,
+            border: 1.5,
+            audio_reactive: false,
             reserved_space: 0.,
+            reserved_pixels: None,
+            safe_area: 0.,
+            side_panels: false,
+            two_columns: false,
             speed: 1.,
+            easing: Easing::Linear,
             no_overlap: true,
+            max_onscreen: 0,
+            lane_gap: None,
+            unique_priority: false,
+            max_lateness: 0.,
+            time_scale: 1.,
+            delay_follow_sub: false,
+            display_delay: 0.,
+            min_display_time: None,
+            max_display_time: None,
+            source_lanes: [0; Source::Unknown as usize + 1],
+            pixel_snap: false,
+            hide_while_seeking: false,
+            overlay_z: 0,
+            paid_lane: true,
+            paid_duration: 10.,
+            paid_color: 0x3a86e8,
+            static_duration: 5.,
+            count_threshold: 0,
+            storm_threshold: 0,
+            storm_speed: None,
+            remember_enabled: false,
+            disable_at: None,
+            disable_after: None,
+            track_new: false,
+            content_key: false,
+            highlight_new: false,
+            #[cfg(feature = "network")]
+            confirm_network: false,
+            anonymize_query: false,
+            confirm_below: 0.,
+            profile: Profile::Normal,
+        }
+    }
+}
+
+impl Options {
+    // 当前选项值，与配置文件中的写法一致
+    pub fn values(&self) -> Value {
+        let mut source_lanes = Source::ALL
+            .into_iter()
+            .filter(|&s| self.source_lanes[s as usize] > 0)
+            .collect::<Vec<_>>();
+        source_lanes.sort_by_key(|&s| self.source_lanes[s as usize]);
+        let c = self.paid_color;
+        let values = json!({
+            "font_size": self.font_size,
+            "transparency": self.transparency,
+            "bright_transparency": self.bright_transparency,
+            "border": self.border,
+            "audio_reactive": self.audio_reactive,
+            "reserved_space": match self.reserved_pixels {
+                Some(px) => json!(format!("{}px", px)),
+                None => json!(self.reserved_space),
+            },
+            "safe_area": self.safe_area,
+            "side_panels": self.side_panels,
+            "two_columns": self.two_columns,
+            "speed": self.speed,
+            "easing": self.easing.name(),
+            "no_overlap": self.no_overlap,
+            "max_onscreen": self.max_onscreen,
+            "lane_gap": self.lane_gap,
+            "unique_priority": self.unique_priority,
+            "max_lateness": self.max_lateness,
+            "time_scale": self.time_scale,
+            "delay_follow_sub": self.delay_follow_sub,
+            "display_delay": self.display_delay,
+            "min_display_time": self.min_display_time,
+            "max_display_time": self.max_display_time,
+            "source_lanes": source_lanes.iter().map(|s| s.name()).collect::<Vec<_>>(),
+            "pixel_snap": self.pixel_snap,
+            "hide_while_seeking": self.hide_while_seeking,
+            "overlay_z": self.overlay_z,
+            "paid_lane": self.paid_lane,
+            "paid_duration": self.paid_duration,
+            "paid_color": format!("{:06X}", (c & 0xff) << 16 | (c & 0xff00) | (c >> 16 & 0xff)),
+            "static_duration": self.static_duration,
+            "count_threshold": self.count_threshold,
+            "storm_threshold": self.storm_threshold,
+            "storm_speed": self.storm_speed,
+            "remember_enabled": self.remember_enabled,
+            "disable_at": self.disable_at.map(|(h, m)| format!("{:02}:{:02}", h, m)),
+            "disable_after": self.disable_after,
+            "track_new": self.track_new,
+            "content_key": self.content_key,
+            "highlight_new": self.highlight_new,
+            "anonymize_query": self.anonymize_query,
+            "confirm_below": self.confirm_below,
+            "profile": self.profile.name(),
+        });
+        #[cfg(feature = "network")]
+        let values = {
+            let mut values = values;
+            values["confirm_network"] = self.confirm_network.into();
+            values
+        };
+        values
+    }
+
+    // 设置一个选项，键不属于 Options 时返回 false
+    pub fn set(&mut self, k: &str, v: &str) -> bool {
+        match k {
+            "font_size" => {
+                if let Some(f) = v.parse().ok().filter(|&f| f > 0.) {
+                    self.font_size = f;
+                }
+            }
+            "transparency" => {
+                if let Ok(t) = v.parse() {
+                    self.transparency = t;
+                }
+            }
+            "bright_transparency" => self.bright_transparency = v.parse().ok(),
+            "border" => {
+                if let Some(b) = v.parse().ok().filter(|b| *b >= 0.) {
+                    self.border = b;
+                }
+            }
+            "audio_reactive" => match v {
+                "yes" => self.audio_reactive = true,
+                "no" => self.audio_reactive = false,
+                _ => (),
+            },
+            "reserved_space" => match v.strip_suffix("px") {
+                Some(px) => {
+                    if let Some(px) = px.parse().ok().filter(|px| *px >= 0.) {
+                        (self.reserved_space, self.reserved_pixels) = (0., Some(px));
+                    }
+                }
+                None => {
+                    if let Some(r) = v.parse().ok().filter(|r| (0. ..1.).contains(r)) {
+                        (self.reserved_space, self.reserved_pixels) = (r, None);
+                    }
+                }
+            },
+            // 接受 5% 或 0.05
+            "safe_area" => {
+                let r = match v.strip_suffix('%') {
+                    Some(pct) => pct.parse::<f64>().ok().map(|pct| pct / 100.),
+                    None => v.parse().ok(),
+                };
+                if let Some(r) = r.filter(|r| (0. ..0.5).contains(r)) {
+                    self.safe_area = r;
+                }
+            }
+            "side_panels" => match v {
+                "yes" => self.side_panels = true,
+                "no" => self.side_panels = false,
+                _ => (),
+            },
+            "two_columns" => match v {
+                "yes" => self.two_columns = true,
+                "no" => self.two_columns = false,
+                _ => (),
+            },
+            "speed" => {
+                if let Some(s) = v.parse().ok().filter(|s| *s > 0.) {
+                    self.speed = s;
+                }
+            }
+            "easing" => {
+                if let Some(e) = Easing::from_name(v) {
+                    self.easing = e;
+                }
+            }
+            "no_overlap" => match v {
+                "yes" => self.no_overlap = true,
+                "no" => self.no_overlap = false,
+                _ => (),
+            },
+            "max_onscreen" => {
+                if let Ok(n) = v.parse() {
+                    self.max_onscreen = n;
+                }
+            }
+            "lane_gap" => {
+                if let Some(g) = v.parse().ok().filter(|g| *g >= 0.) {
+                    self.lane_gap = Some(g);
+                }
+            }
+            "unique_priority" => match v {
+                "yes" => self.unique_priority = true,
+                "no" => self.unique_priority = false,
+                _ => (),
+            },
+            "max_lateness" => {
+                if let Some(l) = v.parse().ok().filter(|l| *l >= 0.) {
+                    self.max_lateness = l;
+                }
+            }
+            "disable_at" => {
+                self.disable_at = v
+                    .split_once(':')
+                    .and_then(|(h, m)| Some((h.parse().ok()?, m.parse().ok()?)))
+                    .filter(|&(h, m)| h < 24 && m < 60)
+            }
+            "disable_after" => self.disable_after = v.parse().ok().filter(|t| *t > 0.),
+            // 接受 0.959 或 23.976/25
+            "time_scale" => {
+                let scale = match v.split_once('/') {
+                    Some((a, b)) => a
+                        .parse::<f64>()
+                        .ok()
+                        .zip(b.parse::<f64>().ok())
+                        .map(|(a, b)| a / b),
+                    None => v.parse().ok(),
+                };
+                if let Some(s) = scale.filter(|s| s.is_finite() && *s > 0.) {
+                    self.time_scale = s;
+                }
+            }
+            "delay_follow_sub" => match v {
+                "yes" => self.delay_follow_sub = true,
+                "no" => self.delay_follow_sub = false,
+                _ => (),
+            },
+            "display_delay" => {
+                if let Some(d) = v.parse().ok().filter(|d| *d >= 0.) {
+                    self.display_delay = d;
+                }
+            }
+            "min_display_time" => self.min_display_time = v.parse().ok().filter(|t| *t > 0.),
+            "max_display_time" => self.max_display_time = v.parse().ok().filter(|t| *t > 0.),
+            "source_lanes" => {
+                self.source_lanes = Default::default();
+                for (band, source) in v
+                    .split(',')
+                    .map(Source::from)
+                    .filter(|&s| s != Source::Unknown)
+                    .enumerate()
+                {
+                    self.source_lanes[source as usize] = band as u8 + 1;
+                }
+            }
+            "pixel_snap" => match v {
+                "yes" => self.pixel_snap = true,
+                "no" => self.pixel_snap = false,
+                _ => (),
+            },
+            "hide_while_seeking" => match v {
+                "yes" => self.hide_while_seeking = true,
+                "no" => self.hide_while_seeking = false,
+                _ => (),
+            },
+            "overlay_z" => {
+                if let Ok(z) = v.parse() {
+                    self.overlay_z = z;
+                }
+            }
+            "paid_lane" => match v {
+                "yes" => self.paid_lane = true,
+                "no" => self.paid_lane = false,
+                _ => (),
+            },
+            "paid_duration" => {
+                if let Some(d) = v.parse().ok().filter(|d| *d > 0.) {
+                    self.paid_duration = d;
+                }
+            }
+            "static_duration" => {
+                if let Some(d) = v.parse().ok().filter(|d| *d > 0.) {
+                    self.static_duration = d;
+                }
+            }
+            "paid_color" => {
+                if let Ok(c) = u32::from_str_radix(v, 16) {
+                    // RRGGBB 转换为 ASS 使用的 BBGGRR
+                    self.paid_color = (c & 0xff) << 16 | (c & 0xff00) | (c >> 16 & 0xff);
+                }
+            }
+            "count_threshold" => {
+                if let Ok(n) = v.parse() {
+                    self.count_threshold = n;
+                }
+            }
+            "storm_threshold" => {
+                if let Ok(n) = v.parse() {
+                    self.storm_threshold = n;
+                }
+            }
+            "storm_speed" => self.storm_speed = v.parse().ok().filter(|s| *s > 0.),
+            "remember_enabled" => match v {
+                "yes" => self.remember_enabled = true,
+                "no" => self.remember_enabled = false,
+                _ => (),
+            },
+            "track_new" => match v {
+                "yes" => self.track_new = true,
+                "no" => self.track_new = false,
+                _ => (),
+            },
+            "content_key" => match v {
+                "yes" => self.content_key = true,
+                "no" => self.content_key = false,
+                _ => (),
+            },
+            "highlight_new" => match v {
+                "yes" => self.highlight_new = true,
+                "no" => self.highlight_new = false,
+                _ => (),
+            },
+            #[cfg(feature = "network")]
+            "confirm_network" => match v {
+                "yes" => self.confirm_network = true,
+                "no" => self.confirm_network = false,
+                _ => (),
+            },
+            "anonymize_query" => match v {
+                "yes" => self.anonymize_query = true,
+                "no" => self.anonymize_query = false,
+                _ => (),
+            },
+            "confirm_below" => {
+                if let Some(c) = v.parse().ok().filter(|c| (0. ..=1.).contains(c)) {
+                    self.confirm_below = c;
+                }
+            }
+            "profile" => {
+                if let Some(p) = Profile::from_name(v) {
+                    self.profile = p;
+                }
+            }
+            _ => return false,
         }
+        true
     }
 }
 
 #[derive(Default)]
 pub struct Filter {
     pub keywords: Vec<String>,
+    // filter_regex 中的正则表达式，匹配任意一个即屏蔽
+    pub patterns: RegexSet,
     pub sources: HashSet<Source>,
     pub sources_rt: Mutex<Option<HashSet<Source>>>,
+    pub kinds: HashSet<Kind>,
+    pub blocklist: Mutex<Blocklist>,
+    // 各来源最多占所列来源弹幕总数的比例
+    pub quotas: HashMap<Source, f64>,
+    // 出现时在 OSD 上提示的关键词，不屏蔽弹幕
+    pub highlights: Vec<String>,
+    // 按标题搜索时优先使用的播放平台
+    pub platforms: Vec<String>,
+    // 该时间内相同的弹幕合并为一条，None 表示不合并
+    pub merge_window: Option<f64>,
+}
+
+// 播放中屏蔽的发送者与弹幕内容，以及导入的关键词
+#[derive(Default)]
+pub struct Blocklist {
+    pub senders: HashSet<String>,
+    pub messages: HashSet<String>,
+    pub keywords: HashSet<String>,
+}
+
+impl Blocklist {
+    pub fn contains(&self, sender: &str, message: &str) -> bool {
+        !sender.is_empty() && self.senders.contains(sender)
+            || self.messages.contains(message)
+            || self.keywords.iter().any(|k| message.contains(k.as_str()))
+    }
+}
+
+// 用于分享的屏蔽列表文件
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SharedFilter {
+    keywords: Vec<String>,
+    senders: Vec<String>,
+    messages: Vec<String>,
+    sources: Vec<String>,
+}
+
+// 导出关键词、来源与运行时屏蔽的发送者和内容
+pub async fn export_filter(filter: &Filter, path: &Path) -> Result<()> {
+    let sources = filter.sources_rt.lock().await;
+    let blocklist = filter.blocklist.lock().await;
+    let sorted = |items: &mut dyn Iterator<Item = &String>| {
+        let mut items = items.cloned().collect::<Vec<_>>();
+        items.sort_unstable();
+        items.dedup();
+        items
+    };
+    let mut sources = sources
+        .as_ref()
+        .unwrap_or(&filter.sources)
+        .iter()
+        .map(|s| s.name().to_string())
+        .collect::<Vec<_>>();
+    sources.sort_unstable();
+    let shared = SharedFilter {
+        keywords: sorted(&mut filter.keywords.iter().chain(&blocklist.keywords)),
+        senders: sorted(&mut blocklist.senders.iter()),
+        messages: sorted(&mut blocklist.messages.iter()),
+        sources,
+    };
+    write(path, serde_json::to_vec_pretty(&shared)?)?;
+    Ok(())
+}
+
+// 合并到运行时的屏蔽列表与来源，返回每一类新增与已有的数量以及无法识别的来源
+pub async fn import_filter(filter: &Filter, path: &Path) -> Result<Vec<String>> {
+    let shared: SharedFilter = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let mut sources = filter.sources_rt.lock().await;
+    let mut blocklist = filter.blocklist.lock().await;
+    // 与配置文件中 filter 相同的关键词也计为已有
+    let (configured, keywords): (Vec<_>, Vec<_>) = shared
+        .keywords
+        .into_iter()
+        .partition(|k| filter.keywords.contains(k));
+    let blocklist = &mut *blocklist;
+    let mut report = Vec::new();
+    for (name, items, set, mut existing) in [
+        (
+            "keywords",
+            keywords,
+            &mut blocklist.keywords,
+            configured.len(),
+        ),
+        ("senders", shared.senders, &mut blocklist.senders, 0),
+        ("messages", shared.messages, &mut blocklist.messages, 0),
+    ] {
+        let mut added = 0;
+        for item in items.into_iter().filter(|i| !i.is_empty()) {
+            if set.insert(item) {
+                added += 1;
+            } else {
+                existing += 1;
+            }
+        }
+        report.push(format!(
+            "{}: {} added, {} already blocked",
+            name, added, existing
+        ));
+    }
+    let sources = sources.get_or_insert_with(|| filter.sources.clone());
+    let (mut added, mut existing, mut unknown) = (0, 0, Vec::new());
+    for name in shared.sources {
+        match Source::from(name.as_str()) {
+            Source::Unknown => unknown.push(name),
+            source if sources.insert(source) => added += 1,
+            _ => existing += 1,
+        }
+    }
+    report.push(format!(
+        "sources: {} added, {} already blocked",
+        added, existing
+    ));
+    if !unknown.is_empty() {
+        report.push(format!("unknown sources: {}", unknown.join(", ")));
+    }
+    Ok(report)
 }
 
 pub fn read_options() -> Result<Option<(Options, Arc<Filter>)>> {
-    let path = expand_path(&format!("~~/script-opts/{}.conf", unsafe { CLIENT_NAME }))?;
+    let path = expand_path(format!("~~/script-opts/{}.conf", unsafe { CLIENT_NAME }))?;
     let file = match File::open(path) {
         Ok(file) => file,
         Err(error) => {
@@ -59,44 +650,94 @@ pub fn read_options() -> Result<Option<(Options, Arc<Filter>)>> {
 
     let mut opts = Options::default();
     let mut filter = Filter::default();
+    let mut headers = HashMap::<Provider, Headers>::new();
+    let mut fixtures = None;
+    let mut rewrites = Vec::new();
+    let mut patterns = Vec::new();
+    let (mut log_file, mut log_max_size, mut log_keep) = (false, 1 << 20, 1);
+    let mut log_dir = None;
     for line in BufReader::new(file).lines() {
         let line = line?;
         if line.starts_with('#') {
             continue;
         }
         if let Some((k, v)) = line.split_once('=') {
+            if opts.set(k, v) {
+                continue;
+            }
             match k {
-                "font_size" => {
-                    if let Some(f) = v.parse().ok().filter(|&f| f > 0.) {
-                        opts.font_size = f;
-                    }
-                }
-                "transparency" => {
-                    if let Ok(t) = v.parse() {
-                        opts.transparency = t;
-                    }
+                // 正则表达式→替换内容，按出现顺序依次应用于标题
+                "rewrite" if !v.is_empty() => match v.split_once('→') {
+                    Some((pattern, replacement)) => match Regex::new(pattern) {
+                        Ok(regex) => rewrites.push((regex, replacement.to_string())),
+                        Err(error) => log_error(&anyhow!("option rewrite: {}", error)),
+                    },
+                    None => log_error(&anyhow!("option rewrite: missing → in {}", v)),
+                },
+                "filter" if !v.is_empty() => filter.keywords.extend(v.split(',').map(Into::into)),
+                // 正则表达式中可能有逗号，每行一个
+                "filter_regex" if !v.is_empty() => match Regex::new(v) {
+                    Ok(_) => patterns.push(v.to_string()),
+                    Err(error) => log_error(&anyhow!("option filter_regex: {}", error)),
+                },
+                "highlight" if !v.is_empty() => {
+                    filter.highlights.extend(v.split(',').map(Into::into))
                 }
-                "reserved_space" => {
-                    if let Some(r) = v.parse().ok().filter(|r| (0. ..1.).contains(r)) {
-                        opts.reserved_space = r;
+                "max_bandwidth" if !v.is_empty() => match v.parse().ok().filter(|&n| n > 0) {
+                    Some(bytes) => set_max_bandwidth(bytes),
+                    None => log_error(&anyhow!("option max_bandwidth: invalid {}", v)),
+                },
+                "max_requests" if !v.is_empty() => match v.parse().ok().filter(|&n| n > 0) {
+                    Some(n) => set_max_requests(n),
+                    None => log_error(&anyhow!("option max_requests: invalid {}", v)),
+                },
+                "merge_duplicates" if !v.is_empty() => {
+                    match v.parse::<f64>().ok().filter(|&w| w > 0.) {
+                        Some(window) => filter.merge_window = Some(window),
+                        None => log_error(&anyhow!("option merge_duplicates: invalid {}", v)),
                     }
                 }
-                "speed" => {
-                    if let Some(s) = v.parse().ok().filter(|s| *s > 0.) {
-                        opts.speed = s;
+                "platform_order" if !v.is_empty() => {
+                    for site in v.split(',') {
+                        if SITES.contains(&site) {
+                            filter.platforms.push(site.into());
+                        } else {
+                            log_error(&anyhow!("option platform_order: unknown {}", site));
+                        }
                     }
                 }
-                "no_overlap" => match v {
-                    "yes" => opts.no_overlap = true,
-                    "no" => opts.no_overlap = false,
-                    _ => (),
-                },
-                "filter" if !v.is_empty() => filter.keywords.extend(v.split(',').map(Into::into)),
                 "filter_source" if !v.is_empty() => filter.sources.extend(
                     v.split(',')
                         .map(Source::from)
                         .filter(|&s| s != Source::Unknown),
                 ),
+                "source_quota" if !v.is_empty() => {
+                    for quota in v.split(',') {
+                        match quota
+                            .split_once(':')
+                            .map(|(source, p)| (Source::from(source), p.parse::<f64>()))
+                        {
+                            Some((source, Ok(p)))
+                                if source != Source::Unknown && (0. ..=100.).contains(&p) =>
+                            {
+                                filter.quotas.insert(source, p / 100.);
+                            }
+                            _ => log_error(&anyhow!("option source_quota: invalid {}", quota)),
+                        }
+                    }
+                }
+                "filter_gift" | "filter_enter" | "filter_lottery" => {
+                    let kind = match k {
+                        "filter_gift" => Kind::Gift,
+                        "filter_enter" => Kind::Enter,
+                        _ => Kind::Lottery,
+                    };
+                    match v {
+                        "yes" => _ = filter.kinds.insert(kind),
+                        "no" => _ = filter.kinds.remove(&kind),
+                        _ => (),
+                    }
+                }
                 "filter_bilibili" if !v.is_empty() => match (|| -> Result<_> {
                     Ok(serde_json::from_reader::<_, Vec<BilibiliFilterRule>>(
                         BufReader::new(File::open(expand_path(v)?)?),
@@ -110,9 +751,78 @@ pub fn read_options() -> Result<Option<(Options, Arc<Filter>)>> {
                     ),
                     Err(error) => log_error(&anyhow!("option filter_bilibili: {}", error)),
                 },
+                "log_file" => match v {
+                    "yes" => log_file = true,
+                    "no" => log_file = false,
+                    _ => (),
+                },
+                "log_dir" if !v.is_empty() => match expand_path(v) {
+                    Ok(dir) => log_dir = Some(dir),
+                    Err(error) => log_error(&anyhow!("option log_dir: {}", error)),
+                },
+                "state_dir" if !v.is_empty() => match expand_path(v) {
+                    Ok(dir) => set_state_dir(dir),
+                    Err(error) => log_error(&anyhow!("option state_dir: {}", error)),
+                },
+                "log_max_size" => {
+                    if let Some(n) = v.parse().ok().filter(|&n| n > 0) {
+                        log_max_size = n;
+                    }
+                }
+                "log_keep" => {
+                    if let Ok(n) = v.parse() {
+                        log_keep = n;
+                    }
+                }
+                "log_verbose" => match v {
+                    "yes" => set_verbose(true),
+                    "no" => set_verbose(false),
+                    _ => (),
+                },
+                "record_responses" | "replay_responses" if !v.is_empty() => match expand_path(v) {
+                    Ok(dir) if k == "record_responses" => fixtures = Some(Fixtures::Record(dir)),
+                    Ok(dir) => fixtures = Some(Fixtures::Replay(dir)),
+                    Err(error) => log_error(&anyhow!("option {}: {}", k, error)),
+                },
+                _ if !v.is_empty() => {
+                    // user_agent_<provider>、referer_<provider>、origin_<provider> 等
+                    let header = |prefix| k.strip_prefix(prefix).and_then(Provider::from_name);
+                    if let Some(provider) = header("user_agent_") {
+                        headers.entry(provider).or_default().user_agent = Some(v.into());
+                    } else if let Some(provider) = header("referer_") {
+                        headers.entry(provider).or_default().referer = Some(v.into());
+                    } else if let Some(provider) = header("origin_") {
+                        headers.entry(provider).or_default().origin = Some(v.into());
+                    } else if k == "dandanplay_app_id" {
+                        headers.entry(Provider::Dandanplay).or_default().app_id = Some(v.into());
+                    } else if k == "dandanplay_app_secret" {
+                        headers.entry(Provider::Dandanplay).or_default().app_secret =
+                            Some(v.into());
+                    }
+                }
                 _ => (),
             }
         }
     }
+    set_headers(headers);
+    set_rewrites(rewrites);
+    // 各表达式均已单独编译过
+    filter.patterns = RegexSet::new(patterns).unwrap();
+    if log_file {
+        match log_dir.map_or_else(
+            || expand_path("~~/danmaku.log"),
+            |dir| Ok(dir.join("danmaku.log")),
+        ) {
+            Ok(path) => set_log_file(LogFile {
+                path,
+                max_size: log_max_size,
+                keep: log_keep,
+            }),
+            Err(error) => log_error(&anyhow!("option log_file: {}", error)),
+        }
+    }
+    if let Some(fixtures) = fixtures {
+        set_fixtures(fixtures);
+    }
     Ok(Some((opts, Arc::new(filter))))
 }
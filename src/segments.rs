@@ -0,0 +1,59 @@
+use crate::danmaku::Danmaku;
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{BufReader, ErrorKind},
+    path::Path,
+};
+
+// SponsorBlock API 返回的片段，只使用其起止时间
+#[derive(Deserialize)]
+struct Segment {
+    segment: [f64; 2],
+}
+
+// 视频旁的 <文件名>.segments.json 中记录的、原视频中有而本文件中剪掉的片段
+// 返回按时间排序并合并重叠部分的区间，没有该文件时为空
+pub fn load_segments(path: &str) -> Result<Vec<(f64, f64)>> {
+    let file = match File::open(Path::new(path).with_extension("segments.json")) {
+        Ok(file) => file,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(error) => return Err(error.into()),
+    };
+    let mut segments = serde_json::from_reader::<_, Vec<Segment>>(BufReader::new(file))?
+        .into_iter()
+        .map(|s| (s.segment[0], s.segment[1]))
+        .filter(|(start, end)| start < end)
+        .collect::<Vec<_>>();
+    segments.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    let mut merged: Vec<(f64, f64)> = Vec::new();
+    for (start, end) in segments {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    Ok(merged)
+}
+
+// 将弹幕时间从原视频的时间轴映射到剪掉片段后的时间轴，丢弃剪掉的片段中的弹幕
+pub fn remap(comments: &mut Vec<Danmaku>, segments: &[(f64, f64)]) {
+    if segments.is_empty() {
+        return;
+    }
+    comments.retain_mut(|comment| {
+        let mut removed = 0.;
+        for &(start, end) in segments {
+            if comment.time < start {
+                break;
+            }
+            if comment.time < end {
+                return false;
+            }
+            removed += end - start;
+        }
+        comment.time -= removed;
+        true
+    });
+}
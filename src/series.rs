@@ -0,0 +1,76 @@
+use crate::{mpv::expand_path, options::Options, CLIENT_NAME};
+use anyhow::Result;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, ErrorKind},
+    sync::Mutex,
+};
+
+// 当前匹配到的剧集的样式
+static STYLE: Mutex<Option<Style>> = Mutex::new(None);
+
+// 按剧集名保存的设置，剧集名为从 media-title 中解析出的名称
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Series {
+    pub style: Style,
+    // 优先使用的 360kan 播放平台，如 qq，覆盖默认的顺序
+    pub platform: Option<String>,
+}
+
+// 覆盖 script-opts 中的样式选项
+#[derive(Default, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct Style {
+    pub font_size: Option<f64>,
+    pub transparency: Option<u8>,
+    pub reserved_space: Option<f64>,
+    pub speed: Option<f64>,
+}
+
+impl Style {
+    fn apply(self, mut options: Options) -> Options {
+        if let Some(f) = self.font_size.filter(|&f| f > 0.) {
+            options.font_size = f;
+        }
+        if let Some(t) = self.transparency {
+            options.transparency = t;
+        }
+        if let Some(r) = self.reserved_space.filter(|r| (0. ..1.).contains(r)) {
+            options.reserved_space = r;
+            options.reserved_pixels = None;
+        }
+        if let Some(s) = self.speed.filter(|&s| s > 0.) {
+            options.speed = s;
+        }
+        options
+    }
+}
+
+pub fn load_series(title: &str) -> Result<Option<Series>> {
+    let path = expand_path(format!("~~/script-opts/{}-series.json", unsafe {
+        CLIENT_NAME
+    }))?;
+    match File::open(path) {
+        Ok(file) => {
+            let mut series: HashMap<String, Series> =
+                serde_json::from_reader(BufReader::new(file))?;
+            Ok(series.remove(title))
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn set_style(style: Option<Style>) {
+    *STYLE.lock().unwrap() = style;
+}
+
+pub fn style(options: Options) -> Options {
+    match *STYLE.lock().unwrap() {
+        Some(style) => style.apply(options),
+        None => options,
+    }
+}
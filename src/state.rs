@@ -0,0 +1,227 @@
+use crate::{
+    log::log_error,
+    mpv::{expand_path, try_get_property_string},
+};
+use anyhow::{anyhow, Result};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, read, remove_file, rename, write, File},
+    io::{BufReader, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    process,
+    sync::OnceLock,
+};
+
+// 未设置 state_dir 时为 None，使用 ~~/danmaku
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileState {
+    pub delay: f64,
+    // 未调整时为 None，使用 time_scale 选项
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stretch: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume: Option<Resume>,
+}
+
+impl Default for FileState {
+    fn default() -> Self {
+        Self {
+            delay: 0.,
+            stretch: None,
+            resume: None,
+        }
+    }
+}
+
+// mpv 为该文件保存 watch later 数据时的状态，恢复播放时还原
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Resume {
+    pub enabled: bool,
+    // 运行时设置的 filter_source，未设置时为 None
+    pub sources: Option<Vec<String>>,
+}
+
+pub fn set_state_dir(dir: PathBuf) {
+    _ = STATE_DIR.set(dir);
+}
+
+fn state_dir() -> Result<PathBuf> {
+    match STATE_DIR.get() {
+        Some(dir) => Ok(dir.clone()),
+        None => expand_path("~~/danmaku"),
+    }
+}
+
+// 先写入同一目录下的临时文件再重命名，同时运行的多个 mpv 不会读到写了一半的文件
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", process::id()));
+    write(&temp, data)?;
+    rename(&temp, path).map_err(|error| {
+        _ = remove_file(&temp);
+        error.into()
+    })
+}
+
+// 与 watch_later 相同，以文件路径的 MD5 作为文件名
+fn state_path(path: &str) -> Result<PathBuf> {
+    let hash = hex::encode(Md5::digest(path));
+    Ok(state_dir()?.join("state").join(format!("{}.json", hash)))
+}
+
+const CONTENT_BLOCK: u64 = 64 * 1024;
+
+// 文件大小与首尾各 64KB 的 MD5，网络共享上的同一文件在不同机器上路径不同时仍能对应
+pub fn content_key(path: &str) -> Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut hasher = Md5::new();
+    hasher.update(size.to_le_bytes());
+    let mut block = Vec::new();
+    (&mut file).take(CONTENT_BLOCK).read_to_end(&mut block)?;
+    hasher.update(&block);
+    if size > CONTENT_BLOCK {
+        // 文件较小时首尾的块不重叠
+        file.seek(SeekFrom::Start((size - CONTENT_BLOCK).max(CONTENT_BLOCK)))?;
+        block.clear();
+        file.take(CONTENT_BLOCK).read_to_end(&mut block)?;
+        hasher.update(&block);
+    }
+    Ok(format!("content:{}", hex::encode(hasher.finalize())))
+}
+
+// mpv 是否为该文件保存了 watch later 数据，文件名为路径的 MD5（大写）
+pub fn has_watch_later(path: &str) -> bool {
+    let key = if try_get_property_string(c"ignore-path-in-watch-later-config").as_deref()
+        == Some("yes")
+    {
+        path.rsplit(['/', '\\']).next().unwrap_or(path)
+    } else {
+        path
+    };
+    let name = hex::encode_upper(Md5::digest(key));
+    // 未设置时使用默认目录，mpv 0.37 之前的选项名为 watch-later-directory
+    let dirs = match try_get_property_string(c"watch-later-dir")
+        .or_else(|| try_get_property_string(c"watch-later-directory"))
+        .filter(|dir| !dir.is_empty())
+    {
+        Some(dir) => vec![dir],
+        None => vec!["~~state/watch_later".into(), "~~/watch_later".into()],
+    };
+    dirs.iter()
+        .any(|dir| expand_path(format!("{}/{}", dir, name)).is_ok_and(|path| path.exists()))
+}
+
+pub fn load_state(path: &str) -> Result<FileState> {
+    match File::open(state_path(path)?) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(FileState::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn save_state(path: &str, state: &FileState) -> Result<()> {
+    let file = state_path(path)?;
+    if *state == FileState::default() {
+        return match remove_file(file) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        };
+    }
+    write_atomic(&file, &serde_json::to_vec(state)?)?;
+    Ok(())
+}
+
+// 与文件无关的状态，保存在配置目录中，不同的 --config-dir 各自独立
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GlobalState {
+    pub enabled: bool,
+}
+
+fn global_path() -> Result<PathBuf> {
+    Ok(state_dir()?.join("global.json"))
+}
+
+pub fn load_global() -> Result<GlobalState> {
+    match File::open(global_path()?) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(GlobalState::default()),
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub fn save_global(state: &GlobalState) -> Result<()> {
+    let file = global_path()?;
+    write_atomic(&file, &serde_json::to_vec(state)?)?;
+    Ok(())
+}
+
+// 以弹幕时间与内容的 MD5 前 4 字节标识一条弹幕
+pub fn fingerprint(time: f64, message: &str) -> u32 {
+    let mut hasher = Md5::new();
+    hasher.update(((time * 1000.).round() as i64).to_le_bytes());
+    hasher.update(message);
+    u32::from_le_bytes(hasher.finalize()[..4].try_into().unwrap())
+}
+
+fn snapshot_path(path: &str) -> Result<PathBuf> {
+    let hash = hex::encode(Md5::digest(path));
+    Ok(state_dir()?.join("snapshots").join(format!("{}.bin", hash)))
+}
+
+// 快照文件头：格式标识与版本、标识的个数
+const SNAPSHOT_MAGIC: &[u8; 4] = b"DMS1";
+const SNAPSHOT_HEADER: usize = 8;
+
+// 上次加载时各弹幕的标识，从未保存过或文件已损坏时返回 None，下次保存时覆盖
+pub fn load_snapshot(path: &str) -> Result<Option<HashSet<u32>>> {
+    let data = match read(snapshot_path(path)?) {
+        Ok(data) => data,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    let body = match data.strip_prefix(SNAPSHOT_MAGIC) {
+        Some(rest) if rest.len() >= SNAPSHOT_HEADER - SNAPSHOT_MAGIC.len() => {
+            let count = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+            let body = &data[SNAPSHOT_HEADER..];
+            if body.len() != count * 4 {
+                log_error(&anyhow!(
+                    "snapshot {}: truncated, {} of {} entries",
+                    path,
+                    body.len() / 4,
+                    count
+                ));
+                return Ok(None);
+            }
+            body
+        }
+        _ => {
+            log_error(&anyhow!("snapshot {}: unknown format", path));
+            return Ok(None);
+        }
+    };
+    Ok(Some(
+        body.chunks_exact(4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+            .collect(),
+    ))
+}
+
+pub fn save_snapshot(path: &str, fingerprints: &[u32]) -> Result<()> {
+    let file = snapshot_path(path)?;
+    let mut data = Vec::with_capacity(SNAPSHOT_HEADER + fingerprints.len() * 4);
+    data.extend(SNAPSHOT_MAGIC);
+    data.extend((fingerprints.len() as u32).to_le_bytes());
+    data.extend(fingerprints.iter().flat_map(|f| f.to_le_bytes()));
+    write_atomic(&file, &data)?;
+    Ok(())
+}
@@ -0,0 +1,140 @@
+use crate::danmaku::{text, Danmaku};
+use anyhow::Result;
+use serde_json::json;
+use std::{collections::HashMap, fs::write, path::Path};
+
+// 统计关键词时忽略的常见虚词
+const STOPWORDS: &[&str] = &[
+    "的", "了", "是", "啊", "吗", "吧", "呢", "呀", "我", "你", "他", "她", "这", "那", "就", "都",
+    "也", "在", "有", "和", "不", "a", "an", "the", "is", "to", "of", "and", "i", "you", "it",
+];
+const MINUTE_KEYWORDS: usize = 3;
+const TOTAL_KEYWORDS: usize = 20;
+
+// 按标点与空白切分弹幕，同一字符的重复（哈哈哈哈、66666）统一为三个字符
+fn tokens(message: &str) -> Vec<String> {
+    let mut tokens = message
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let s = s.to_lowercase();
+            let mut chars = s.chars();
+            match chars.next() {
+                Some(first) if s.chars().count() > 3 && chars.all(|c| c == first) => {
+                    first.to_string().repeat(3)
+                }
+                _ => s,
+            }
+        })
+        .filter(|s| !STOPWORDS.contains(&s.as_str()))
+        .collect::<Vec<_>>();
+    // 每条弹幕中的关键词只计一次
+    tokens.sort_unstable();
+    tokens.dedup();
+    tokens
+}
+
+fn top(counts: HashMap<String, usize>, n: usize) -> Vec<(String, usize)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.truncate(n);
+    counts
+}
+
+// 出现次数最多的 n 个关键词，不包括已屏蔽的弹幕
+pub fn keywords(comments: &[Danmaku], n: usize) -> Vec<(String, usize)> {
+    let text = text();
+    let mut counts = HashMap::new();
+    for comment in comments.iter().filter(|c| !c.blocked) {
+        for token in tokens(comment.message.get(&text)) {
+            *counts.entry(token).or_default() += 1;
+        }
+    }
+    top(counts, n)
+}
+
+// 每分钟的弹幕数量与关键词
+pub fn export_histogram(comments: &[Danmaku], path: &Path) -> Result<()> {
+    let text = text();
+    let mut minutes = Vec::<(usize, HashMap<String, usize>)>::new();
+    let mut total = HashMap::new();
+    for comment in comments.iter().filter(|c| !c.blocked) {
+        let minute = (comment.time.max(0.) / 60.) as usize;
+        if minute >= minutes.len() {
+            minutes.resize_with(minute + 1, Default::default);
+        }
+        let (count, counts) = &mut minutes[minute];
+        *count += 1;
+        for token in tokens(comment.message.get(&text)) {
+            *counts.entry(token.clone()).or_default() += 1;
+            *total.entry(token).or_default() += 1;
+        }
+    }
+    let keywords = |counts, n| {
+        top(counts, n)
+            .into_iter()
+            .map(|(token, count)| json!({ "token": token, "count": count }))
+            .collect::<Vec<_>>()
+    };
+    let json = json!({
+        "minutes": minutes
+            .into_iter()
+            .enumerate()
+            .map(|(minute, (count, counts))| {
+                json!({
+                    "minute": minute,
+                    "count": count,
+                    "keywords": keywords(counts, MINUTE_KEYWORDS),
+                })
+            })
+            .collect::<Vec<_>>(),
+        "keywords": keywords(total, TOTAL_KEYWORDS),
+    });
+    write(path, serde_json::to_vec_pretty(&json)?)?;
+    Ok(())
+}
+
+// 弹幕中表示精彩片段的词
+const MARKERS: &[&str] = &["开始了", "高能", "泪目", "名场面", "来了", "燃起来了"];
+// 间隔不超过 GAP 秒的标记弹幕属于同一片段，至少 MIN_CLUSTER 条才生成章节
+const GAP: f64 = 15.;
+const MIN_CLUSTER: usize = 5;
+// 相邻章节的最小间隔
+const MIN_DISTANCE: f64 = 60.;
+
+// 标记弹幕聚集处的时间与标题，标题为片段中最多的标记
+pub fn highlights(comments: &[Danmaku]) -> Vec<(f64, String)> {
+    let text = text();
+    let marked = comments.iter().filter(|c| !c.blocked).filter_map(|c| {
+        let message = c.message.get(&text);
+        MARKERS
+            .iter()
+            .position(|m| message.contains(m))
+            .map(|i| (c.time, i))
+    });
+    let mut highlights = Vec::new();
+    let mut cluster = Vec::<(f64, usize)>::new();
+    let mut flush = |cluster: &mut Vec<(f64, usize)>| {
+        if cluster.len() >= MIN_CLUSTER
+            && !highlights
+                .last()
+                .is_some_and(|&(time, _)| cluster[0].0 - time < MIN_DISTANCE)
+        {
+            let mut counts = [0; MARKERS.len()];
+            for &(_, i) in cluster.iter() {
+                counts[i] += 1;
+            }
+            let marker = (0..MARKERS.len()).max_by_key(|&i| counts[i]).unwrap();
+            highlights.push((cluster[0].0, MARKERS[marker].to_string()));
+        }
+        cluster.clear();
+    };
+    for (time, marker) in marked {
+        if cluster.last().is_some_and(|&(last, _)| time - last > GAP) {
+            flush(&mut cluster);
+        }
+        cluster.push((time, marker));
+    }
+    flush(&mut cluster);
+    highlights
+}
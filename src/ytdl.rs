@@ -0,0 +1,15 @@
+use crate::mpv::try_get_property_string;
+use serde::Deserialize;
+
+// yt-dlp 输出的元数据中用于识别原始网站的字段
+#[derive(Deserialize)]
+pub struct YtdlInfo {
+    pub webpage_url: Option<String>,
+    pub extractor: Option<String>,
+}
+
+// 通过 ytdl_hook 播放时，读取其保存的 yt-dlp 输出
+pub fn ytdl_info() -> Option<YtdlInfo> {
+    let stdout = try_get_property_string(c"user-data/mpv/ytdl/json-subprocess-result/stdout")?;
+    serde_json::from_str(&stdout).ok()
+}
@@ -0,0 +1,169 @@
+// 在无界面的 mpv 中加载编译出的插件，通过 JSON IPC 发送 script-message 并检查 user-data
+// 需要 PATH 中有 mpv 0.36 及以上版本，使用 cargo test --features mpv-test 运行
+#![cfg(all(feature = "mpv-test", unix))]
+
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::PathBuf,
+    process::{self, Child, Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Mpv {
+    child: Child,
+    stream: BufReader<UnixStream>,
+    dir: PathBuf,
+    request_id: u64,
+}
+
+impl Mpv {
+    // 以空的配置目录启动 mpv，插件以 danmaku 为客户端名加载
+    fn start(name: &str) -> Self {
+        // 测试程序位于 target/<profile>/deps，cdylib 位于 target/<profile>
+        let exe = env::current_exe().unwrap();
+        let lib = exe.parent().unwrap().parent().unwrap().join(format!(
+            "{}danmaku{}",
+            env::consts::DLL_PREFIX,
+            env::consts::DLL_SUFFIX
+        ));
+        let dir = env::temp_dir().join(format!("danmaku-{}-{}", name, process::id()));
+        _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join(format!("danmaku{}", env::consts::DLL_SUFFIX));
+        fs::copy(&lib, &script).unwrap_or_else(|e| panic!("{}: {}", lib.display(), e));
+        let socket = dir.join("ipc");
+        let child = Command::new("mpv")
+            .arg("--no-config")
+            .arg(format!("--config-dir={}", dir.display()))
+            .args([
+                "--idle=yes",
+                "--no-video",
+                "--ao=null",
+                "--vo=null",
+                "--msg-level=all=warn",
+            ])
+            .arg(format!("--input-ipc-server={}", socket.display()))
+            .arg(format!("--script={}", script.display()))
+            .stdin(Stdio::null())
+            .spawn()
+            .expect("mpv not found in PATH");
+
+        let start = Instant::now();
+        let stream = loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => break stream,
+                Err(_) if start.elapsed() < TIMEOUT => sleep(Duration::from_millis(50)),
+                Err(error) => panic!("connect to mpv: {}", error),
+            }
+        };
+        stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+        let mut mpv = Self {
+            child,
+            stream: BufReader::new(stream),
+            dir,
+            request_id: 0,
+        };
+        // 插件在 mpv 启动后异步初始化，等待其写入 user-data
+        mpv.wait_for("user-data/danmaku/enabled", json!(false));
+        mpv
+    }
+
+    // 发送命令并返回对应的回复，跳过其间的事件
+    fn command(&mut self, args: Value) -> Value {
+        self.request_id += 1;
+        let request = json!({ "command": args, "request_id": self.request_id });
+        let stream = self.stream.get_mut();
+        writeln!(stream, "{}", request).unwrap();
+        loop {
+            let mut line = String::new();
+            self.stream.read_line(&mut line).unwrap();
+            let reply = serde_json::from_str::<Value>(&line).unwrap();
+            if reply["request_id"] == self.request_id {
+                return reply;
+            }
+        }
+    }
+
+    fn get(&mut self, name: &str) -> Value {
+        self.command(json!(["get_property", name]))["data"].clone()
+    }
+
+    // script-message 由插件异步处理，轮询属性直到等于 expected
+    fn wait_for(&mut self, name: &str, expected: Value) {
+        let start = Instant::now();
+        loop {
+            let value = self.get(name);
+            if value == expected {
+                return;
+            }
+            if start.elapsed() > TIMEOUT {
+                panic!("{}: expected {}, got {}", name, expected, value);
+            }
+            sleep(Duration::from_millis(20));
+        }
+    }
+
+    fn message(&mut self, args: &[&str]) {
+        let mut command = vec!["script-message-to", "danmaku"];
+        command.extend(args);
+        assert_eq!(self.command(json!(command))["error"], "success");
+    }
+}
+
+impl Drop for Mpv {
+    fn drop(&mut self) {
+        _ = writeln!(self.stream.get_mut(), "{}", json!({ "command": ["quit"] }));
+        let start = Instant::now();
+        while self.child.try_wait().unwrap().is_none() {
+            if start.elapsed() > TIMEOUT {
+                _ = self.child.kill();
+                break;
+            }
+            sleep(Duration::from_millis(20));
+        }
+        _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn script_messages() {
+    let mut mpv = Mpv::start("messages");
+    assert_eq!(mpv.get("user-data/danmaku/profile"), "normal");
+
+    mpv.message(&["danmaku-profile-cycle"]);
+    mpv.wait_for("user-data/danmaku/profile", json!("full"));
+
+    mpv.message(&["danmaku-delay", "1.5"]);
+    mpv.wait_for("user-data/danmaku/delay", json!(1.5));
+    mpv.message(&["danmaku-delay", "-0.5"]);
+    mpv.wait_for("user-data/danmaku/delay", json!(1.));
+
+    mpv.message(&["toggle-danmaku"]);
+    mpv.wait_for("user-data/danmaku/enabled", json!(true));
+    mpv.message(&["toggle-danmaku"]);
+    mpv.wait_for("user-data/danmaku/enabled", json!(false));
+}
+
+#[test]
+fn remote_user_data() {
+    let mut mpv = Mpv::start("remote");
+    // 外部写入 user-data 时插件随之切换，之后的操作从写入的状态开始
+    mpv.command(json!([
+        "set_property",
+        "user-data/danmaku/profile",
+        "sparse"
+    ]));
+    mpv.command(json!(["set_property", "user-data/danmaku/enabled", true]));
+    // 属性变化与 script-message 的处理顺序不确定
+    sleep(Duration::from_millis(200));
+    mpv.message(&["danmaku-profile-cycle"]);
+    mpv.wait_for("user-data/danmaku/profile", json!("normal"));
+    mpv.message(&["toggle-danmaku"]);
+    mpv.wait_for("user-data/danmaku/enabled", json!(false));
+}